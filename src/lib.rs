@@ -0,0 +1,34 @@
+mod sstv;
+
+pub use sstv::{
+  SSTVSetup,
+  SSTVDecoder,
+  DecodedImage,
+  HeaderInfo,
+  SSTVEncoder,
+  SSTVStream,
+  StreamEvent,
+  FollowReader,
+  SlantCorrection,
+  ModeCandidate,
+  encode_fsk_id,
+  SSTVError,
+  Spec,
+  ColFmt,
+  ColorRange,
+  VIS_MAP,
+  all_modes,
+  mode_by_name,
+  vis_code,
+  Image,
+  RGB,
+  ResizeFilter,
+  TextStyle,
+  TextPosition,
+  TextOverlay,
+  PngMetadata,
+  WavWriter,
+  SampleFormat,
+  read_wav_header,
+  calc_lum,
+};
@@ -1,26 +1,708 @@
-mod sstv;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+
+use russtv::{
+  SSTVSetup, SSTVDecoder, SSTVEncoder, SSTVError, SSTVStream, StreamEvent, FollowReader, Image, RGB, ResizeFilter,
+  PngMetadata, Spec, ColFmt, TextOverlay, TextPosition, WavWriter, SampleFormat, read_wav_header, all_modes, mode_by_name, vis_code,
+};
+
+
+// Exit codes for scripts
+const EXIT_FAILURE: i32 = 1;  // no signal found or it couldn't be decoded
+const EXIT_USAGE: i32 = 2;
+const EXIT_IO: i32 = 3;  // files couldn't be read or written
+
+// Lowest classifier confidence trusted to decode a transmission without
+// a header
+const MIN_CONFIDENCE: f32 = 0.3;
+
+const USAGE: &str = "\
+Usage: russtv <command> [options]
+
+Commands:
+  decode <audio> [output]   Decode the first SSTV image in a recording
+  encode <image>            Encode a PNG or PPM image as SSTV audio
+  info <audio>              Show the header of the first transmission
+  modes                     List the supported modes
+  scan <audio>              Find every transmission in a recording
+  help [command]            Show help for a command
+
+Run 'russtv help <command>' for the options of each command.";
+
+const DECODE_USAGE: &str = "\
+Usage: russtv decode <audio> [options]
+
+Decodes the first SSTV image in a recording. If no header is found, the
+mode is identified from the timing of the sync pulses. Passing - as the
+audio reads a WAV stream from stdin and saves every image received as
+numbered files, each decoded as the mode its header gives, so --mode,
+--start and --no-header can't be used with it. --follow does the same
+for a WAV file that is still being recorded. PNG output records the
+mode, source audio, SNR and slant correction in text chunks.
+
+Options:
+  -o, --output <file>     Image to write (default out.png)
+  -f, --format <format>   png or ppm (default from the output extension)
+  -m, --mode <mode>       Decode as this mode whatever the VIS says, given
+                          by name (\"Martin 1\", m1) or VIS number. If no
+                          header is found, decoding starts at the first
+                          sync pulse instead
+  -s, --start <seconds>   Only look for a transmission after this time
+  -u, --update <lines>    Rewrite the output every this many rows while
+                          decoding, to watch the picture arrive
+  --no-header             With --mode, skip the header search and start at
+                          the first sync pulse after --start
+  --follow                Keep reading the WAV file as it grows, saving
+                          every image received, until interrupted";
+
+const ENCODE_USAGE: &str = "\
+Usage: russtv encode <image> --mode <mode> [options]
+
+Encodes a PNG or binary PPM image as SSTV audio. Images that aren't the
+mode's size are scaled to fit, keeping their shape, with black borders.
+
+Options:
+  -m, --mode <mode>       Mode to encode with, by name or VIS number
+  -o, --output <file>     WAV file to write (default out.wav)
+  -r, --rate <hz>         Sample rate (default 44100)
+  -c, --call <callsign>   Stamp the callsign on the picture
+  -t, --text <template>   Text to stamp, where {call}, {mode}, {vis},
+                          {date} and {time} (UTC) are filled in
+                          (default \"{call}\" when --call is given)
+  --text-pos <position>   top-left, top, top-right, bottom-left, bottom
+                          or bottom-right (default top-left)
+  --fsk-id                Send the --call callsign as an FSK ID after the
+                          image";
+
+const INFO_USAGE: &str = "\
+Usage: russtv info <audio>
+
+Shows the position, VIS, mode, frequency offset and SNR of the first
+transmission's header without decoding the image. If there is no header,
+lists the modes matching the timing of the sync pulses instead.";
+
+const MODES_USAGE: &str = "\
+Usage: russtv modes
+
+Lists every supported mode with its VIS code, size and duration.";
+
+const SCAN_USAGE: &str = "\
+Usage: russtv scan <audio> [options]
+
+Finds and decodes every transmission in a recording.
+
+Options:
+  -o, --output <file>     Save the images as numbered files, e.g. out-1.png
+                          (default: numbered PNGs next to the audio)";
+
+
+enum CliError {
+  Usage(String),
+  Sstv(SSTVError),
+}
+
+impl From<SSTVError> for CliError {
+  fn from(err: SSTVError) -> Self {
+    CliError::Sstv(err)
+  }
+}
+
+impl From<std::io::Error> for CliError {
+  fn from(err: std::io::Error) -> Self {
+    CliError::Sstv(SSTVError::Io(err))
+  }
+}
+
+
+struct Args {
+  positional: Vec<String>,
+  // Long name and value of each option given
+  options: Vec<(String, String)>,
+  // Long names of the flags given
+  flags: Vec<String>,
+  help: bool,
+}
+
+impl Args {
+  fn parse(args: &[String], known: &[(&str, &str)], flags: &[&str]) -> Result<Args, CliError> {
+    //"""Splits arguments into positionals, options and flags, where known
+    //lists the short and long names of the options that take a value"""
+    let mut parsed = Args { positional: Vec::new(), options: Vec::new(), flags: Vec::new(), help: false };
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+      if arg == "-h" || arg == "--help" {
+        parsed.help = true;
+        continue;
+      }
+      if arg == "-" || !arg.starts_with('-') {
+        parsed.positional.push(arg.clone());
+        continue;
+      }
+      if flags.contains(&arg.as_str()) {
+        parsed.flags.push(arg.trim_start_matches('-').to_string());
+        continue;
+      }
+
+      // Accept both --name value and --name=value
+      let (name, inline) = match arg.split_once('=') {
+        Some((name, value)) => (name, Some(value.to_string())),
+        None => (arg.as_str(), None),
+      };
+      let long = match known.iter().find(|(short, long)| name == *short || name == *long) {
+        Some((_, long)) => long.trim_start_matches('-').to_string(),
+        None => return Err(CliError::Usage(format!("unknown option {}", name))),
+      };
+      let value = match inline.or_else(|| iter.next().cloned()) {
+        Some(value) => value,
+        None => return Err(CliError::Usage(format!("{} needs a value", name))),
+      };
+      parsed.options.push((long, value));
+    }
+    Ok(parsed)
+  }
+
+  fn option(&self, long: &str) -> Option<&str> {
+    // The last value given wins
+    self.options.iter().rev().find(|(name, _)| name == long).map(|(_, value)| value.as_str())
+  }
+
+  fn flag(&self, long: &str) -> bool {
+    self.flags.iter().any(|name| name == long)
+  }
+
+  fn input(&self) -> Result<&str, CliError> {
+    match self.positional.first() {
+      Some(input) => Ok(input),
+      None => Err(CliError::Usage("missing input file".to_string())),
+    }
+  }
+}
+
 
 fn main() {
-  let args: Vec<String> = std::env::args().collect();
+  let args: Vec<String> = std::env::args().skip(1).collect();
+
+  let (command, rest) = match args.split_first() {
+    Some((command, rest)) => (command.as_str(), rest),
+    None => {
+      eprintln!("{}", USAGE);
+      process::exit(EXIT_USAGE);
+    },
+  };
+
+  let (usage, result) = match command {
+    "decode" => (DECODE_USAGE, cmd_decode(rest)),
+    "encode" => (ENCODE_USAGE, cmd_encode(rest)),
+    "info" => (INFO_USAGE, cmd_info(rest)),
+    "modes" => (MODES_USAGE, cmd_modes(rest)),
+    "scan" => (SCAN_USAGE, cmd_scan(rest)),
+    "help" | "-h" | "--help" => {
+      println!("{}", command_usage(rest.first().map(|s| s.as_str())));
+      return;
+    },
+    // Older releases took the audio and output as the only arguments, so
+    // an existing file is decoded rather than taken for a typo
+    _ if command == "-" || Path::new(command).is_file() => (DECODE_USAGE, cmd_decode(&args)),
+    _ => (USAGE, Err(CliError::Usage(format!("unknown command {}", command)))),
+  };
+
+  match result {
+    Ok(()) => (),
+    Err(CliError::Usage(msg)) => {
+      eprintln!("Error: {}\n\n{}", msg, usage);
+      process::exit(EXIT_USAGE);
+    },
+    Err(CliError::Sstv(err)) => {
+      eprintln!("{}", err);
+      let code = match err {
+        SSTVError::Io(_) | SSTVError::AudioFormat(_) => EXIT_IO,
+        _ => EXIT_FAILURE,
+      };
+      process::exit(code);
+    },
+  }
+}
+
+
+fn command_usage(command: Option<&str>) -> &'static str {
+  match command {
+    Some("decode") => DECODE_USAGE,
+    Some("encode") => ENCODE_USAGE,
+    Some("info") => INFO_USAGE,
+    Some("modes") => MODES_USAGE,
+    Some("scan") => SCAN_USAGE,
+    _ => USAGE,
+  }
+}
+
+
+fn parse_mode(name: &str) -> Result<Spec, CliError> {
+  match mode_by_name(name) {
+    Some(mode) => Ok(mode),
+    None => Err(CliError::Usage(format!("unknown mode {} (see 'russtv modes')", name))),
+  }
+}
+
+
+fn save_image(image: &Image, filename: &str, format: Option<&str>, metadata: &PngMetadata) -> Result<(), CliError> {
+  write_image(image, filename, format, metadata)?;
+  println!("Image written to {}", filename);
+  Ok(())
+}
+
+
+fn write_image(image: &Image, filename: &str, format: Option<&str>, metadata: &PngMetadata) -> Result<(), CliError> {
+  // Format defaults to the one matching the file extension. PPM has no
+  // room for the metadata
+  let format = format.map(|f| f.to_lowercase()).unwrap_or_else(|| {
+    if filename.to_lowercase().ends_with(".ppm") { "ppm".to_string() } else { "png".to_string() }
+  });
+
+  match format.as_str() {
+    "png" => image.write_file_png_with_metadata(filename, metadata)?,
+    "ppm" => image.write_file(filename)?,
+    _ => return Err(CliError::Usage(format!("unknown image format {}", format))),
+  }
+  Ok(())
+}
+
+
+// Partly decoded image rewritten to the output every few rows
+struct Snapshot {
+  image: Image,
+  filename: String,
+  // Rows between rewrites of the file, if it's rewritten at all
+  every: Option<usize>,
+}
+
+impl Snapshot {
+  fn new(mode: &Spec, filename: &str, every: Option<usize>) -> Self {
+    let mut image = Image::new(mode.LINE_COUNT as u32, mode.LINE_WIDTH as u32);
+    image.set_greyscale(mode.COLOR == ColFmt::BW);
+    Snapshot {
+      image,
+      filename: filename.to_string(),
+      every,
+    }
+  }
+
+  fn add_row(&mut self, y: usize, row: &[u8], format: Option<&str>) {
+    self.image.set_row(y as u32, row);
+    if self.every.is_some_and(|every| (y + 1).is_multiple_of(every)) {
+      // A failed snapshot shouldn't stop the decode
+      if let Err(CliError::Sstv(err)) = write_image(&self.image, &self.filename, format, &PngMetadata::new()) {
+        eprintln!("Couldn't write {}: {}", self.filename, err);
+      }
+    }
+  }
+}
+
+
+fn cmd_decode(args: &[String]) -> Result<(), CliError> {
+  let args = Args::parse(
+    args,
+    &[("-o", "--output"), ("-f", "--format"), ("-m", "--mode"), ("-s", "--start"), ("-u", "--update")],
+    &["--no-header", "--follow"],
+  )?;
+  if args.help {
+    println!("{}", DECODE_USAGE);
+    return Ok(());
+  }
+
+  let input = args.input()?;
+  let output = args.option("output").or(args.positional.get(1).map(|s| s.as_str())).unwrap_or("out.png");
+  let format = args.option("format");
+  let mode = args.option("mode").map(parse_mode).transpose()?;
+  let start = match args.option("start") {
+    Some(start) => match start.parse::<f32>() {
+      Ok(secs) if secs >= 0.0 => secs,
+      _ => return Err(CliError::Usage(format!("invalid start time {}", start))),
+    },
+    None => 0.0,
+  };
+  let update = match args.option("update") {
+    Some(lines) => match lines.parse::<usize>() {
+      Ok(lines) if lines > 0 => Some(lines),
+      _ => return Err(CliError::Usage(format!("invalid update interval {}", lines))),
+    },
+    None => None,
+  };
+  // Catch a bad format before decoding rather than at the first snapshot
+  if let Some(format) = format {
+    if !["png", "ppm"].contains(&format.to_lowercase().as_str()) {
+      return Err(CliError::Usage(format!("unknown image format {}", format)));
+    }
+  }
+
+  // "-" decodes a WAV stream from stdin until it closes, and --follow a
+  // WAV file as it's written
+  if input == "-" || args.flag("follow") {
+    // The stream decoder follows the VIS of each transmission as it comes
+    for option in ["mode", "start"] {
+      if args.option(option).is_some() {
+        return Err(CliError::Usage(format!("--{} can't be used with stdin or --follow", option)));
+      }
+    }
+    if args.flag("no-header") {
+      return Err(CliError::Usage("--no-header can't be used with stdin or --follow".to_string()));
+    }
+    if input == "-" {
+      return decode_stream(&mut std::io::stdin().lock(), "stdin", output, format, update);
+    }
+    let mut reader = FollowReader::new(BufReader::new(File::open(input)?));
+    return decode_stream(&mut reader, input, output, format, update);
+  }
 
-  let input_file: &String = match args.get(1) {
-    Some(filename) => filename,
-    _ => panic!("Must give audofile as input")
+  let setup: SSTVSetup = SSTVSetup::new(input)?;
+  let start_sample = (start * setup.sample_rate() as f32) as usize;
+  let forced_mode = mode.is_some();
+  // Whether the image follows a header, rather than the first sync pulse
+  let mut has_header = true;
+  let decoder: SSTVDecoder = match mode {
+    Some(mode) if args.flag("no-header") => {
+      has_header = false;
+      setup.decode_without_header(mode, start_sample)?
+    },
+    Some(mode) => match setup.decode_as(mode.clone(), start_sample) {
+      // Recover a transmission that was joined after its header
+      Err(SSTVError::HeaderNotFound) => {
+        println!("No header found, starting at the first sync pulse");
+        has_header = false;
+        setup.decode_without_header(mode, start_sample)?
+      },
+      result => result?,
+    },
+    None if args.flag("no-header") => return Err(CliError::Usage("--no-header needs a --mode".to_string())),
+    None => match setup.decode_from(start_sample) {
+      Err(SSTVError::HeaderNotFound) => {
+        let mode = guess_mode(&setup, start_sample)?;
+        has_header = false;
+        setup.decode_without_header(mode, start_sample)?
+      },
+      result => result?,
+    },
   };
 
-  let defualt_out = "out.png".to_string();
-  let out_file: &String = args.get(2).unwrap_or(&defualt_out);
+  if !has_header {
+    println!("Using SSTV mode {} from sync pulse at {:.2}s", decoder.mode().NAME, decoder.image_start_time().as_secs_f32());
+  } else if forced_mode {
+    println!("Using SSTV mode {}", decoder.mode().NAME);
+  } else {
+    println!("Detected SSTV mode {}", decoder.mode().NAME);
+  }
 
+  let mut snapshot = Snapshot::new(decoder.mode(), output, update);
+  let image = match decoder.decode_image_rows(|y, row| snapshot.add_row(y, row, format)) {
+    Ok(image) => image,
+    // Keep the rows sent before the recording stopped
+    Err(err @ SSTVError::TruncatedAudio { .. }) => {
+      println!("{}, saving the rows decoded", err);
+      snapshot.image
+    },
+    Err(err) => return Err(err.into()),
+  };
 
-  match main_decode(input_file, out_file) {
-    Err(s) => println!("{}", s),
-    Ok(()) => println!("Done.")
+  if decoder.freq_offset().abs() >= 1.0 {
+    println!("Corrected frequency offset of {:+.1} Hz", decoder.freq_offset());
+  }
+  if let Some(fit) = decoder.slant() {
+    println!("Corrected slant: sample rate {:.1} Hz ({:+.1} ppm)", fit.sample_rate, fit.ppm);
+  }
+  if let Some(offset) = decoder.final_freq_offset() {
+    if (offset - decoder.freq_offset()).abs() >= 1.0 {
+      println!("Frequency offset drifted to {:+.1} Hz", offset);
+    }
+  }
+
+  // Without a header there's no leader tone to measure the SNR from
+  let header = if has_header { setup.header_info_from(start_sample).ok() } else { None };
+  let sample_rate = setup.sample_rate() as f64;
+  let start_time = match &header {
+    Some(info) => Duration::from_secs_f64(info.start as f64 / sample_rate),
+    None => decoder.image_start_time(),
+  };
+  let mut metadata = PngMetadata::from_decode(decoder.mode(), input);
+  metadata.set_start(start_time);
+  if let Some(snr) = header.and_then(|info| info.snr) {
+    metadata.set_snr(snr);
+  }
+  if let Some(slant) = decoder.slant() {
+    metadata.set_slant(&slant);
+  }
+  if let Some(callsign) = decoder.fsk_id() {
+    println!("FSK ID: {}", callsign);
+    metadata.set_fsk_id(&callsign);
+  }
+  save_image(&image, output, format, &metadata)
+}
+
+
+fn guess_mode(setup: &SSTVSetup, start: usize) -> Result<Spec, CliError> {
+  // Identify the mode of a transmission with no header from its sync pulses
+  match setup.classify(start).into_iter().next() {
+    Some(best) if best.confidence >= MIN_CONFIDENCE => {
+      println!("No header found, sync timing matches {} (confidence {:.2})", best.mode.NAME, best.confidence);
+      Ok(best.mode)
+    },
+    _ => Err(SSTVError::HeaderNotFound.into()),
   }
 }
 
-fn main_decode(infile: &String, outfile: &str) -> Result<(), String> {
-  let setup: sstv::SSTVSetup = sstv::SSTVSetup::new(infile);
-  let decoder: sstv::SSTVDecoder = setup.decode()?;
 
-  decoder.save_png(outfile)
-}
\ No newline at end of file
+fn decode_stream<R: Read>(reader: &mut R, source: &str, outfile: &str, format: Option<&str>, update: Option<usize>) -> Result<(), CliError> {
+  let (channels, sample_rate, sample_format) = read_wav_header(reader)?;
+
+  let mut stream = SSTVStream::new(sample_rate, channels);
+  let mut count = 0;
+  let mut snapshot: Option<Snapshot> = None;
+  let mut metadata = PngMetadata::new();
+
+  stream.read_pcm(reader, sample_format, |event| match event {
+    StreamEvent::HeaderDetected(mode) => {
+      println!("Receiving {}", mode.NAME);
+      metadata = PngMetadata::from_decode(&mode, source);
+      snapshot = update.map(|every| Snapshot::new(&mode, &numbered_path(outfile, count + 1), Some(every)));
+    },
+    StreamEvent::LineDecoded(y, row) => {
+      if let Some(snapshot) = snapshot.as_mut() {
+        snapshot.add_row(y, &row, format);
+      }
+    },
+    StreamEvent::ImageComplete(image) | StreamEvent::ImageTruncated(image) => {
+      count += 1;
+      let filename = numbered_path(outfile, count);
+      // Keep listening even if one image can't be saved
+      if let Err(CliError::Sstv(err)) = save_image(&image, &filename, format, &metadata) {
+        eprintln!("Couldn't write {}: {}", filename, err);
+      }
+    },
+    StreamEvent::SignalLost => println!("Signal lost"),
+  })?;
+
+  if count == 0 {
+    return Err(SSTVError::HeaderNotFound.into());
+  }
+  Ok(())
+}
+
+
+fn cmd_encode(args: &[String]) -> Result<(), CliError> {
+  let args = Args::parse(
+    args,
+    &[("-o", "--output"), ("-m", "--mode"), ("-r", "--rate"), ("-c", "--call"), ("-t", "--text"), ("--text-pos", "--text-pos")],
+    &["--fsk-id"],
+  )?;
+  if args.help {
+    println!("{}", ENCODE_USAGE);
+    return Ok(());
+  }
+
+  let input = args.input()?;
+  let output = args.option("output").unwrap_or("out.wav");
+  let mode = match args.option("mode") {
+    Some(name) => parse_mode(name)?,
+    None => return Err(CliError::Usage("encode needs a --mode".to_string())),
+  };
+  let sample_rate = match args.option("rate").unwrap_or("44100").parse::<u32>() {
+    Ok(rate) if rate >= 8000 => rate,
+    _ => return Err(CliError::Usage("sample rate must be a whole number of at least 8000".to_string())),
+  };
+
+  let overlay = parse_overlay(&args)?;
+  if args.flag("fsk-id") && args.option("call").is_none() {
+    return Err(CliError::Usage("--fsk-id needs a --call".to_string()));
+  }
+
+  let mut image = Image::open(input)?;
+  if image.width() != mode.LINE_WIDTH as u32 || image.height() != mode.LINE_COUNT as u32 {
+    println!("Fitting {}x{} image to {}x{}", image.width(), image.height(), mode.LINE_WIDTH, mode.LINE_COUNT);
+    image = image.fit_to_mode(&mode, ResizeFilter::Lanczos3, RGB { r: 0, g: 0, b: 0 });
+  }
+  let mut encoder = match &overlay {
+    Some(overlay) => SSTVEncoder::with_overlay(&image, mode, sample_rate, overlay)?,
+    None => SSTVEncoder::new(&image, mode, sample_rate)?,
+  };
+  if args.flag("fsk-id") {
+    encoder.set_fsk_id(args.option("call").unwrap_or_default())?;
+  }
+  let samples = encoder.encode()?;
+  WavWriter::new(1, sample_rate, SampleFormat::PCM16).write_file_i16(output, &samples)?;
+  println!("Audio written to {}", output);
+  Ok(())
+}
+
+
+fn parse_overlay(args: &Args) -> Result<Option<TextOverlay>, CliError> {
+  // Text stamped on the picture, if any was asked for
+  let call = args.option("call");
+  let template = match (args.option("text"), call) {
+    (Some(text), _) => text,
+    (None, Some(_)) => "{call}",
+    (None, None) => return Ok(None),
+  };
+  if template.contains("{call}") && call.is_none() {
+    return Err(CliError::Usage("text uses {call} but no --call was given".to_string()));
+  }
+
+  let mut overlay = TextOverlay::new(template);
+  if let Some(call) = call {
+    overlay.set_field("call", call);
+  }
+  overlay.position = match args.option("text-pos").unwrap_or("top-left") {
+    "top-left" => TextPosition::TopLeft,
+    "top" => TextPosition::Top,
+    "top-right" => TextPosition::TopRight,
+    "bottom-left" => TextPosition::BottomLeft,
+    "bottom" => TextPosition::Bottom,
+    "bottom-right" => TextPosition::BottomRight,
+    pos => return Err(CliError::Usage(format!("unknown text position {}", pos))),
+  };
+  Ok(Some(overlay))
+}
+
+
+fn format_vis(vis: usize) -> String {
+  // MMSSTV's extended codes are written as their two bytes
+  if vis > 0xFF { format!("0x{:04X}", vis) } else { vis.to_string() }
+}
+
+
+fn format_time(secs: f64) -> String {
+  format!("{:02}:{:02}:{:04.1}", (secs / 3600.0) as u64, (secs / 60.0) as u64 % 60, secs % 60.0)
+}
+
+
+fn cmd_info(args: &[String]) -> Result<(), CliError> {
+  let args = Args::parse(args, &[], &[])?;
+  if args.help {
+    println!("{}", INFO_USAGE);
+    return Ok(());
+  }
+
+  let setup: SSTVSetup = SSTVSetup::new(args.input()?)?;
+  let info = match setup.header_info() {
+    Err(SSTVError::HeaderNotFound) => {
+      println!("No header found. Modes matching the sync pulse timing:");
+      let candidates = setup.classify(0);
+      for candidate in candidates.iter().take(5) {
+        println!("  {:<12} {:.2}", candidate.mode.NAME, candidate.confidence);
+      }
+      if candidates.is_empty() {
+        println!("  none");
+      }
+      return Err(SSTVError::HeaderNotFound.into());
+    },
+    result => result?,
+  };
+  let sample_rate = setup.sample_rate() as f64;
+
+  println!("Header:      {} - {} (samples {}-{})",
+    format_time(info.start as f64 / sample_rate), format_time(info.end as f64 / sample_rate),
+    info.start, info.end);
+  println!("VIS:         {}", format_vis(info.vis));
+  match &info.mode {
+    Some(mode) => println!("Mode:        {} ({}x{}, {:.1} s)",
+      mode.NAME, mode.LINE_WIDTH, mode.LINE_COUNT, mode.image_time()),
+    None => println!("Mode:        unsupported"),
+  }
+  println!("Freq offset: {:+.1} Hz", info.freq_offset);
+  if let Some(snr) = info.snr {
+    println!("SNR:         {:.1} dB", snr);
+  }
+
+  if info.mode.is_none() {
+    return Err(SSTVError::UnsupportedVis(info.vis).into());
+  }
+  Ok(())
+}
+
+
+fn cmd_modes(args: &[String]) -> Result<(), CliError> {
+  let args = Args::parse(args, &[], &[])?;
+  if args.help {
+    println!("{}", MODES_USAGE);
+    return Ok(());
+  }
+  if let Some(extra) = args.positional.first() {
+    return Err(CliError::Usage(format!("unexpected argument {}", extra)));
+  }
+
+  println!("{:>6}  {:<12} {:>9}  {:>8}", "VIS", "Mode", "Size", "Duration");
+  for mode in all_modes() {
+    let vis = vis_code(&mode).map_or("-".to_string(), format_vis);
+    println!("{:>6}  {:<12} {:>9}  {:>7.1}s",
+      vis, mode.NAME, format!("{}x{}", mode.LINE_WIDTH, mode.LINE_COUNT), mode.image_time());
+  }
+  Ok(())
+}
+
+
+fn cmd_scan(args: &[String]) -> Result<(), CliError> {
+  let args = Args::parse(args, &[("-o", "--output")], &[])?;
+  if args.help {
+    println!("{}", SCAN_USAGE);
+    return Ok(());
+  }
+
+  let input = args.input()?;
+  // overnight.wav -> overnight-1.png, overnight-2.png...
+  let default_output = Path::new(input).with_extension("png").to_string_lossy().into_owned();
+  let output = args.option("output").unwrap_or(&default_output);
+  let setup: SSTVSetup = SSTVSetup::new(input)?;
+  let results = setup.decode_all();
+  if results.is_empty() {
+    return Err(SSTVError::HeaderNotFound.into());
+  }
+
+  let mut images = Vec::new();
+  let mut last_error = None;
+  for result in results {
+    match result {
+      Ok(decoded) => images.push(decoded),
+      Err(err) => {
+        eprintln!("Skipping transmission: {}", err);
+        last_error = Some(err);
+      },
+    }
+  }
+  // Only fail when nothing at all could be decoded
+  if let Some(err) = last_error.filter(|_| images.is_empty()) {
+    return Err(err.into());
+  }
+
+  for (idx, decoded) in images.iter().enumerate() {
+    println!("Image {}: {} at {} (samples {}-{}){}",
+      idx + 1, decoded.mode.NAME, format_time(decoded.timestamp.as_secs_f64()),
+      decoded.start, decoded.end,
+      decoded.fsk_id.as_ref().map(|id| format!(" from {}", id)).unwrap_or_default());
+    let mut metadata = PngMetadata::from_decode(&decoded.mode, input);
+    metadata.set_start(decoded.timestamp);
+    if let Some(snr) = decoded.snr {
+      metadata.set_snr(snr);
+    }
+    if let Some(slant) = &decoded.slant {
+      metadata.set_slant(slant);
+    }
+    if let Some(callsign) = &decoded.fsk_id {
+      metadata.set_fsk_id(callsign);
+    }
+    save_image(&decoded.image, &numbered_path(output, idx + 1), None, &metadata)?;
+  }
+  Ok(())
+}
+
+
+fn numbered_path(outfile: &str, count: usize) -> String {
+  // out.png -> out-1.png, leaving dots in directory names alone
+  let path = Path::new(outfile);
+  let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+  let mut name = format!("{}-{}", stem, count);
+  if let Some(ext) = path.extension() {
+    name = format!("{}.{}", name, ext.to_string_lossy());
+  }
+  path.with_file_name(name).to_string_lossy().into_owned()
+}
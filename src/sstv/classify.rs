@@ -0,0 +1,362 @@
+// Identifies the mode of a transmission from the timing of its sync pulses,
+// for when the VIS header was missed
+
+use crate::sstv::spec;
+
+
+// Shortest run below the threshold counted as a sync pulse, in seconds
+const MIN_PULSE: f32 = 0.002;
+// Gaps this short inside a pulse are treated as noise, in seconds
+const MAX_GAP: f32 = 0.001;
+// Allowed error in a pulse's width as a share of the expected width
+const WIDTH_TOLERANCE: f32 = 0.3;
+// Allowed error in the spacing of two pulses, in seconds plus a share of
+// the line time for sound card clock error
+const SPACING_TOLERANCE: f32 = 0.0005;
+const SPACING_DRIFT: f32 = 0.002;
+// Weight given to a spacing of one, two and three lines, so that a mode
+// sending a sync every other line of another mode doesn't match it
+const SPACING_WEIGHTS: [f32; 3] = [1.0, 0.5, 0.25];
+// Lines either side of a mode's line count that its syncs may stop at and
+// still be taken as the end of the image
+const END_TOLERANCE: f32 = 2.0;
+// Share of the longest image time searched for the end of a transmission
+const IMAGE_MARGIN: f32 = 1.05;
+// Least difference in Hz between the separators of neighbouring lines
+const SEP_SWING: f32 = 400.0;
+
+
+#[derive(Debug, Clone)]
+pub struct ModeCandidate {
+  pub mode: spec::Spec,
+  // 0 to 1, from how well the pulse widths and spacings fit the mode
+  pub confidence: f32,
+}
+
+
+pub fn find_pulses(track: &[f32], sample_rate: u32, threshold: f32) -> Vec<(usize, usize)> {
+  //"""Finds the (start, width) in samples of every run of the track below
+  //the sync threshold that is long enough to be a sync pulse"""
+  let min_pulse = (MIN_PULSE * sample_rate as f32) as usize;
+  let max_gap = (MAX_GAP * sample_rate as f32) as usize;
+
+  let mut pulses: Vec<(usize, usize)> = Vec::new();
+  let mut run_start: Option<usize> = None;
+  let mut last_low = 0;
+
+  for (idx, freq) in track.iter().enumerate() {
+    if *freq < threshold {
+      if run_start.is_none() {
+        run_start = Some(idx);
+      }
+      last_low = idx;
+    } else if let Some(start) = run_start {
+      if idx - last_low > max_gap {
+        let width = last_low + 1 - start;
+        if width >= min_pulse {
+          pulses.push((start, width));
+        }
+        run_start = None;
+      }
+    }
+  }
+
+  if let Some(start) = run_start {
+    let width = last_low + 1 - start;
+    if width >= min_pulse {
+      pulses.push((start, width));
+    }
+  }
+  pulses
+}
+
+
+struct Chain {
+  // Sample of each sync along the chain
+  syncs: Vec<f32>,
+  // Lines matched, weighted by how far apart the syncs were
+  matched: f32,
+}
+
+
+fn mode_syncs(mode: &spec::Spec, pulses: &[(usize, usize)], sample_rate: u32) -> Vec<f32> {
+  //"""Start of every pulse of about the mode's sync width"""
+  let width = mode.SYNC_PULSE * sample_rate as f32;
+  pulses.iter()
+    .filter(|(_, w)| (*w as f32 - width).abs() <= WIDTH_TOLERANCE * width)
+    .map(|(start, _)| *start as f32)
+    .collect()
+}
+
+
+fn follow_chains(mode: &spec::Spec, syncs: &[f32], sample_rate: u32) -> Vec<Chain> {
+  //"""Splits the syncs into chains, each following on from the nearest sync
+  //a whole number of lines on"""
+  let line = mode.LINE_TIME * sample_rate as f32;
+  let tolerance = (SPACING_TOLERANCE + SPACING_DRIFT * mode.LINE_TIME) * sample_rate as f32;
+  let max_lines = SPACING_WEIGHTS.len() as f32;
+
+  // Nearest following sync a whole number of lines on, and its weight.
+  // Noise can add pulses in between, so look past the next one
+  let links: Vec<Option<(usize, f32)>> = syncs.iter().enumerate().map(|(idx, sync)| {
+    syncs[idx + 1..].iter().enumerate()
+      .take_while(|(_, other)| **other - sync <= (max_lines + 0.5) * line)
+      .find_map(|(offset, other)| {
+        let lines = ((other - sync) / line).round();
+        let error = (other - sync - lines * line).abs();
+        if lines >= 1.0 && error <= tolerance {
+          Some((idx + 1 + offset, SPACING_WEIGHTS[lines as usize - 1]))
+        } else {
+          None
+        }
+      })
+  }).collect();
+
+  let mut in_chain = vec![false; syncs.len()];
+  let mut chains = Vec::new();
+
+  for start in 0..syncs.len() {
+    if in_chain[start] {
+      continue;
+    }
+
+    let mut chain = Chain { syncs: vec![syncs[start]], matched: 0.0 };
+    let mut end = start;
+    while let Some((next, weight)) = links[end] {
+      in_chain[next] = true;
+      chain.syncs.push(syncs[next]);
+      chain.matched += weight;
+      end = next;
+    }
+    chains.push(chain);
+  }
+  chains
+}
+
+
+fn score_mode(mode: &spec::Spec, pulses: &[(usize, usize)], sample_rate: u32) -> f32 {
+  //"""Follows the longest chain of pulses of the mode's width spaced a whole
+  //number of lines apart. The score is the share of line slots along the
+  //chain holding one of its syncs, times the share of pulses of that width
+  //along the chain that belong to it"""
+  let line = mode.LINE_TIME * sample_rate as f32;
+  let tolerance = (SPACING_TOLERANCE + SPACING_DRIFT * mode.LINE_TIME) * sample_rate as f32;
+  let syncs = mode_syncs(mode, pulses, sample_rate);
+
+  let mut best = 0.0;
+  for chain in follow_chains(mode, &syncs, sample_rate) {
+    // A couple of matches could be chance
+    if chain.matched < 3.0 {
+      continue;
+    }
+
+    let (first, last) = (chain.syncs[0], chain.syncs[chain.syncs.len() - 1]);
+    let slots = ((last - first) / line).round().max(1.0);
+    let coverage = (chain.matched / slots).min(1.0);
+    let in_span = syncs.iter()
+      .filter(|sync| **sync >= first - tolerance && **sync <= last + tolerance)
+      .count();
+    let purity = chain.syncs.len() as f32 / in_span as f32;
+
+    best = f32::max(best, coverage * purity);
+  }
+  best
+}
+
+
+fn longest_chain(mode: &spec::Spec, pulses: &[(usize, usize)], sample_rate: u32) -> Option<Chain> {
+  //"""Chain of the mode's syncs matching the most lines"""
+  let syncs = mode_syncs(mode, pulses, sample_rate);
+  follow_chains(mode, &syncs, sample_rate).into_iter()
+    .max_by(|a, b| a.matched.total_cmp(&b.matched))
+}
+
+
+fn lines_sent(mode: &spec::Spec, pulses: &[(usize, usize)], sample_rate: u32) -> f32 {
+  //"""Number of lines from the first to the last sync of the mode's longest
+  //chain"""
+  match longest_chain(mode, pulses, sample_rate) {
+    Some(chain) => {
+      let line = mode.LINE_TIME * sample_rate as f32;
+      ((chain.syncs[chain.syncs.len() - 1] - chain.syncs[0]) / line).round() + 1.0
+    },
+    None => 0.0,
+  }
+}
+
+
+fn separator_swing(mode: &spec::Spec, track: &[f32], pulses: &[(usize, usize)], sample_rate: u32) -> f32 {
+  //"""Share of neighbouring lines whose separator pulses differ like the
+  //alternating R-Y and B-Y separators of Robot 36 style modes"""
+  let Some(chain) = longest_chain(mode, pulses, sample_rate) else {
+    return 0.0;
+  };
+  let sample_rate = sample_rate as f32;
+  let line = mode.LINE_TIME * sample_rate;
+  // Middle half of the separator, clear of the scans either side
+  let sep_start = mode.CHAN_OFFSETS[1] - mode.SEP_PORCH - mode.SEP_PULSE;
+  let from = ((sep_start + mode.SEP_PULSE / 4.0) * sample_rate) as usize;
+  let to = ((sep_start + mode.SEP_PULSE * 3.0 / 4.0) * sample_rate) as usize;
+
+  let separator = |sync: f32| {
+    let window = track.get(sync as usize + from..sync as usize + to)?;
+    Some(window.iter().sum::<f32>() / window.len() as f32)
+  };
+
+  let mut pairs = 0;
+  let mut swings = 0;
+  for pair in chain.syncs.windows(2) {
+    // Only syncs one line apart are a known pair of even and odd lines
+    if ((pair[1] - pair[0]) / line).round() != 1.0 {
+      continue;
+    }
+    if let (Some(a), Some(b)) = (separator(pair[0]), separator(pair[1])) {
+      pairs += 1;
+      if (a - b).abs() >= SEP_SWING {
+        swings += 1;
+      }
+    }
+  }
+
+  if pairs == 0 {
+    0.0
+  } else {
+    swings as f32 / pairs as f32
+  }
+}
+
+
+fn sort_candidates(candidates: &mut [ModeCandidate]) {
+  // Sync timing alone can't tell Martin 3 from Martin 1 and so on, so
+  // prefer the mode sending more lines, whose top half is right either way
+  candidates.sort_by(|a, b| {
+    b.confidence.total_cmp(&a.confidence).then(b.mode.sync_lines().cmp(&a.mode.sync_lines()))
+  });
+}
+
+
+pub fn rank_modes(pulses: &[(usize, usize)], sample_rate: u32) -> Vec<ModeCandidate> {
+  //"""Scores every known mode against the sync pulses found, best first"""
+  let mut candidates: Vec<ModeCandidate> = spec::all_modes().into_iter()
+    .map(|mode| {
+      let confidence = score_mode(&mode, pulses, sample_rate);
+      ModeCandidate { mode, confidence }
+    })
+    .filter(|candidate| candidate.confidence > 0.0)
+    .collect();
+
+  sort_candidates(&mut candidates);
+  candidates
+}
+
+
+fn fit_tied(tied: &mut [ModeCandidate], track: &[f32], sample_rate: u32, threshold: f32) {
+  //"""Lowers the confidence of the modes sharing a sync timing whose line
+  //count or separator pulses don't fit the transmission"""
+  // Look far enough to see where the longest of the modes would end
+  let longest = tied.iter().map(|candidate| candidate.mode.image_time()).fold(0.0, f32::max);
+  let end = std::cmp::min(track.len(), (longest * IMAGE_MARGIN * sample_rate as f32) as usize);
+  let pulses = find_pulses(&track[..end], sample_rate, threshold);
+
+  // Syncs stopping at one mode's line count rule out the longer modes,
+  // while syncs running past it rule that mode out
+  let sent: Vec<f32> = tied.iter().map(|candidate| lines_sent(&candidate.mode, &pulses, sample_rate)).collect();
+  let ends_with = |candidate: &ModeCandidate, sent: f32| {
+    (sent - candidate.mode.sync_lines() as f32).abs() <= END_TOLERANCE
+  };
+  let ended = tied.iter().zip(&sent).any(|(candidate, sent)| ends_with(candidate, *sent));
+  for (candidate, sent) in tied.iter_mut().zip(&sent) {
+    let lines = candidate.mode.sync_lines() as f32;
+    if *sent > lines + END_TOLERANCE {
+      candidate.confidence *= lines / sent;
+    } else if ended && !ends_with(candidate, *sent) {
+      candidate.confidence *= sent / lines;
+    }
+  }
+
+  // Robot 36 and Robot 36 BW only differ in what follows the Y scan
+  let has_separator = |mode: &spec::Spec| mode.HAS_ALT_SCAN && mode.SEP_PULSE > 0.0;
+  if let Some(separated) = tied.iter().find(|candidate| has_separator(&candidate.mode)) {
+    if tied.iter().any(|candidate| !has_separator(&candidate.mode)) {
+      let swing = separator_swing(&separated.mode, track, &pulses, sample_rate);
+      for candidate in tied.iter_mut() {
+        candidate.confidence *= if has_separator(&candidate.mode) { swing } else { 1.0 - swing };
+      }
+    }
+  }
+}
+
+
+pub fn break_ties(candidates: &mut Vec<ModeCandidate>, track: &[f32], sample_rate: u32, threshold: f32) {
+  //"""Reorders modes scoring the same from their sync timing, from how well
+  //their line count and separator pulses fit the track following the start
+  //of the transmission"""
+  let mut idx = 0;
+  while idx < candidates.len() {
+    let confidence = candidates[idx].confidence;
+    let tied = candidates[idx..].iter().take_while(|candidate| candidate.confidence == confidence).count();
+    if tied > 1 {
+      fit_tied(&mut candidates[idx..idx + tied], track, sample_rate, threshold);
+    }
+    idx += tied;
+  }
+
+  candidates.retain(|candidate| candidate.confidence > 0.0);
+  sort_candidates(candidates);
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const RATE: u32 = 10000;
+
+
+  fn pulse_track(mode: &spec::Spec, lines: usize, tail: f32) -> Vec<f32> {
+    // Sync pulses at 1200 Hz every line, with mid grey between
+    let line = (mode.LINE_TIME * RATE as f32) as usize;
+    let pulse = (mode.SYNC_PULSE * RATE as f32) as usize;
+    let mut track = vec![1900.0; line * lines + (tail * RATE as f32) as usize];
+    for idx in 0..lines {
+      track[idx * line..idx * line + pulse].fill(1200.0);
+    }
+    track
+  }
+
+
+  #[test]
+  fn pulses_bridge_short_gaps_and_drop_short_runs() {
+    let mut track = vec![1900.0; 1000];
+    // A 9 ms pulse broken by a single sample of noise
+    track[100..190].fill(1200.0);
+    track[140] = 1900.0;
+    // A click too short to be a sync
+    track[500..510].fill(1200.0);
+    assert_eq!(find_pulses(&track, RATE, 1350.0), vec![(100, 90)]);
+  }
+
+
+  #[test]
+  fn line_count_breaks_ties() {
+    let martin1 = spec::mode_by_name("Martin 1").unwrap();
+    for (lines, expected) in [(256, "Martin 1"), (128, "Martin 3")] {
+      // Another second of silence after the image
+      let track = pulse_track(&martin1, lines, 1.0);
+      let pulses = find_pulses(&track[..(30 * RATE) as usize], RATE, 1350.0);
+      let mut candidates = rank_modes(&pulses, RATE);
+      assert_eq!(candidates[0].confidence, candidates[1].confidence);
+
+      break_ties(&mut candidates, &track, RATE, 1350.0);
+      assert_eq!(candidates[0].mode.NAME, expected);
+      assert!(candidates[1].confidence < candidates[0].confidence);
+    }
+  }
+
+
+  #[test]
+  fn too_few_pulses_match_nothing() {
+    let track = pulse_track(&spec::mode_by_name("Martin 1").unwrap(), 3, 0.0);
+    assert!(rank_modes(&find_pulses(&track, RATE, 1350.0), RATE).is_empty());
+  }
+}
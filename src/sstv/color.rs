@@ -0,0 +1,138 @@
+// Colour space conversion between the scan channels of a mode and RGB
+
+use crate::sstv::spec::ColFmt;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorRange {
+  // Y, Cb and Cr all use 0-255
+  Full,
+  // Y uses 16-235 and Cb/Cr use 16-240
+  Studio,
+}
+
+
+fn clamp_byte(value: f32) -> u8 {
+  value.round().clamp(0.0, 255.0) as u8
+}
+
+
+pub fn ycbcr_to_rgb(y: u8, cb: u8, cr: u8, range: ColorRange) -> (u8, u8, u8) {
+  // ITU-R BT.601
+  let (y, cb, cr) = match range {
+    ColorRange::Full => (y as f32, cb as f32 - 128.0, cr as f32 - 128.0),
+    ColorRange::Studio => (
+      (y as f32 - 16.0) * 255.0 / 219.0,
+      (cb as f32 - 128.0) * 255.0 / 224.0,
+      (cr as f32 - 128.0) * 255.0 / 224.0,
+    ),
+  };
+
+  let r = y + 1.402 * cr;
+  let g = y - 0.344136 * cb - 0.714136 * cr;
+  let b = y + 1.772 * cb;
+  (clamp_byte(r), clamp_byte(g), clamp_byte(b))
+}
+
+
+pub fn rgb_to_ycbcr(r: u8, g: u8, b: u8, range: ColorRange) -> (u8, u8, u8) {
+  // ITU-R BT.601
+  let (r, g, b) = (r as f32, g as f32, b as f32);
+  let y = 0.299 * r + 0.587 * g + 0.114 * b;
+  let cb = -0.168736 * r - 0.331264 * g + 0.5 * b;
+  let cr = 0.5 * r - 0.418688 * g - 0.081312 * b;
+
+  match range {
+    ColorRange::Full => (clamp_byte(y), clamp_byte(cb + 128.0), clamp_byte(cr + 128.0)),
+    ColorRange::Studio => (
+      clamp_byte(16.0 + y * 219.0 / 255.0),
+      clamp_byte(128.0 + cb * 224.0 / 255.0),
+      clamp_byte(128.0 + cr * 224.0 / 255.0),
+    ),
+  }
+}
+
+
+pub fn to_rgb(color: &ColFmt, pixel: (u8, u8, u8), range: ColorRange) -> (u8, u8, u8) {
+  //"""Converts a pixel given in the channel order of the mode into RGB"""
+  match color {
+    ColFmt::RGB => pixel,
+    ColFmt::GBR => (pixel.2, pixel.0, pixel.1),
+    // Channels are ordered Y, Cb, Cr
+    ColFmt::YUV => ycbcr_to_rgb(pixel.0, pixel.1, pixel.2, range),
+    ColFmt::BW => {
+      let (lum, _, _) = ycbcr_to_rgb(pixel.0, 128, 128, range);
+      (lum, lum, lum)
+    },
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Colour bars: white, yellow, cyan, green, magenta, red, blue, black
+  const BARS: [(u8, u8, u8); 8] = [
+    (1, 1, 1), (1, 1, 0), (0, 1, 1), (0, 1, 0), (1, 0, 1), (1, 0, 0), (0, 0, 1), (0, 0, 0),
+  ];
+
+  // Y, Cb and Cr of the bars from the BT.601 tables
+  const FULL_100: [(u8, u8, u8); 8] = [
+    (255, 128, 128), (226, 0, 149), (179, 171, 0), (150, 44, 21),
+    (105, 212, 235), (76, 85, 255), (29, 255, 107), (0, 128, 128),
+  ];
+  const FULL_75: [(u8, u8, u8); 8] = [
+    (191, 128, 128), (170, 32, 144), (134, 160, 32), (113, 65, 48),
+    (79, 191, 208), (57, 96, 224), (22, 224, 112), (0, 128, 128),
+  ];
+  const STUDIO_100: [(u8, u8, u8); 8] = [
+    (235, 128, 128), (210, 16, 146), (170, 166, 16), (145, 54, 34),
+    (106, 202, 222), (81, 90, 240), (41, 240, 110), (16, 128, 128),
+  ];
+  const STUDIO_75: [(u8, u8, u8); 8] = [
+    (180, 128, 128), (162, 44, 142), (131, 156, 44), (112, 72, 58),
+    (84, 184, 198), (65, 100, 212), (35, 212, 114), (16, 128, 128),
+  ];
+
+  fn close(a: (u8, u8, u8), b: (u8, u8, u8), tolerance: u8) -> bool {
+    a.0.abs_diff(b.0) <= tolerance && a.1.abs_diff(b.1) <= tolerance && a.2.abs_diff(b.2) <= tolerance
+  }
+
+  fn check_bars(level: u8, range: ColorRange, expected: &[(u8, u8, u8); 8]) {
+    for (bar, ycbcr) in BARS.iter().zip(expected) {
+      let rgb = (bar.0 * level, bar.1 * level, bar.2 * level);
+      let converted = rgb_to_ycbcr(rgb.0, rgb.1, rgb.2, range);
+      assert!(close(converted, *ycbcr, 1), "{:?} gave {:?}, not {:?}", rgb, converted, ycbcr);
+      let back = ycbcr_to_rgb(ycbcr.0, ycbcr.1, ycbcr.2, range);
+      assert!(close(back, rgb, 2), "{:?} gave {:?}, not {:?}", ycbcr, back, rgb);
+    }
+  }
+
+  #[test]
+  fn full_range_bars() {
+    check_bars(255, ColorRange::Full, &FULL_100);
+    check_bars(191, ColorRange::Full, &FULL_75);
+  }
+
+  #[test]
+  fn studio_range_bars() {
+    check_bars(255, ColorRange::Studio, &STUDIO_100);
+    check_bars(191, ColorRange::Studio, &STUDIO_75);
+  }
+
+  #[test]
+  fn round_trip() {
+    for range in [ColorRange::Full, ColorRange::Studio] {
+      for r in (0..=255).step_by(15) {
+        for g in (0..=255).step_by(15) {
+          for b in (0..=255).step_by(15) {
+            let (y, cb, cr) = rgb_to_ycbcr(r, g, b, range);
+            let back = ycbcr_to_rgb(y, cb, cr, range);
+            assert!(close(back, (r, g, b), 3), "{:?} {:?} came back as {:?}", range, (r, g, b), back);
+          }
+        }
+      }
+    }
+  }
+}
@@ -1,3 +1,6 @@
+use crate::sstv::inflate;
+
+
 pub fn crc(buf: &[u8]) -> u32 {
   let mut crc_table = [0; 256];
 
@@ -15,6 +18,9 @@ pub fn crc(buf: &[u8]) -> u32 {
 }
 
 pub fn encode_data_zlib(data: &[u8]) -> Vec<u8> {
-  let compressed = deflate::deflate_bytes_zlib(data);
-  return compressed;
+  deflate::deflate_bytes_zlib(data)
+}
+
+pub fn decode_data_zlib(data: &[u8]) -> std::io::Result<Vec<u8>> {
+  inflate::decode_zlib(data)
 }
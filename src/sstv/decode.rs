@@ -2,105 +2,348 @@
 
 use crate::sstv::spec;
 use crate::sstv::img;
-
-
-type PixelVec = Vec<Vec<Vec<usize>>>;
+use crate::sstv::color::{self, ColorRange};
+use crate::sstv::demod::{self, mean_freq};
+use crate::sstv::error::SSTVError;
+use crate::sstv::slant::{self, SlantCorrection};
+use crate::sstv::classify::{self, ModeCandidate};
+use crate::sstv::fskid;
+use std::cell::Cell;
+use std::time::Duration;
+
+
+pub(crate) type PixelVec = Vec<Vec<Vec<usize>>>;
+
+// Nominal tones the thresholds below are measured from
+const LEADER_FREQ: f32 = 1900.0;
+const SYNC_FREQ: f32 = 1200.0;
+// Frequency separating sync pulses from the image
+const SYNC_THRESHOLD: f32 = 1350.0;
+// Frequency separating the 1500 Hz Robot separator sent before an R-Y scan
+// from the 2300 Hz one sent before a B-Y scan
+const SEP_THRESHOLD: f32 = 1900.0;
+// Largest receiver mistuning the header search allows for
+const MAX_FREQ_OFFSET: f32 = 250.0;
+// Share of each sync pulse's measured offset taken into the tracked offset
+const AFC_TRACK_RATE: f32 = 0.1;
+
+// Audio examined when identifying a mode from its sync pulses, in seconds
+const CLASSIFY_TIME: f32 = 30.0;
+
+// Extra audio kept past the nominal end of an image so that a slow sound
+// card clock doesn't cut off its last lines
+const IMAGE_END_MARGIN: f32 = 1.05;
 
 
 pub fn calc_lum(freq: f32) -> usize {
-  use std::cmp;
   // Converts SSTV pixel frequency range into 0-255 luminance byte
-  let output = ((255.0 / (2300.0 - 1500.0)) * (freq - 1500.0)) as usize;
-  return   cmp::min(cmp::max(output, 0), 255);
+  ((255.0 / (2300.0 - 1500.0)) * (freq - 1500.0)).clamp(0.0, 255.0) as usize
 }
 
 
-fn peak_fft_freq(data: &[i16], sample_rate: u32) -> f32 {
-
-  use easyfft::prelude::*;
-  use spectrum_analyzer::windows::hann_window;
-
-  //"""Finds the peak frequency from a section of audio data"""
-  let abs_vals: Vec<f32> = data.into_iter().map(|p| *p as f32).collect();
-  let windowed_data = hann_window(abs_vals.as_ref());
-
-  let fft: Vec<u32> = windowed_data.real_fft().iter().map(|v| v.norm() as u32).collect();
-
-  let max: &u32 = fft.iter().max().unwrap();
-  let index: usize = fft.iter().position(|element| element == max).unwrap();
-
-  let y1 = if index <= 0 {fft[index]}  else {fft[index-1]} as f32;
-  let y3 =  if index + 1 >= fft.len() {fft[index]} else {fft[index+1]} as f32;
-
-  // interpolate max with adjacent values
-  let peak =  ((y3 - y1) as f32 / (y3 - y1 + *max as f32) + index as f32) as f32;
-
-  return peak as f32 * sample_rate as f32 / abs_vals.len() as f32;
-}
-
 #[derive(Clone)]
 pub struct SSTVSetup {
   // mode: Option<spec::Spec>,
   sample_rate: u32,
+  // Audio the track was demodulated from, kept for signal measurements
   samples: Vec<i16>,
+  // Instantaneous frequency of each sample
+  pub(crate) track: Vec<f32>,
 }
 
 pub struct SSTVDecoder {
   mode: spec::Spec,
   sample_rate: u32,
-  samples: Vec<i16>,
-  header_end: usize,
+  pub(crate) track: Vec<f32>,
+  // First sample after the VIS, or where decoding was told to start
+  image_start: usize,
+  // Receiver mistuning measured from the calibration header
+  freq_offset: f32,
+  color_range: ColorRange,
+  slant_correction: bool,
+  // Slant corrected by the most recent decode
+  slant: Cell<Option<SlantCorrection>>,
+  // Frequency offset tracked to the last line of the most recent decode
+  final_freq_offset: Cell<Option<f32>>,
+}
+
+pub struct HeaderInfo {
+  // Sample offsets of the start and end of the calibration header
+  pub start: usize,
+  pub end: usize,
+  pub vis: usize,
+  // None if the VIS isn't one of the supported modes
+  pub mode: Option<spec::Spec>,
+  pub freq_offset: f32,
+  // Signal to noise ratio of the first leader tone in dB, if the audio
+  // samples are available
+  pub snr: Option<f32>,
+}
+
+pub struct DecodedImage {
+  pub image: img::Image,
+  pub mode: spec::Spec,
+  // Sample offsets of the start of the calibration header and the end of
+  // the last line
+  pub start: usize,
+  pub end: usize,
+  // Time of the start of the transmission from the start of the recording
+  pub timestamp: Duration,
+  // Signal to noise ratio of the leader tone in dB
+  pub snr: Option<f32>,
+  // Clock error corrected while decoding, if slant was found
+  pub slant: Option<SlantCorrection>,
+  // Receiver mistuning measured from the header, and where it had drifted
+  // to by the last line
+  pub freq_offset: f32,
+  pub final_freq_offset: f32,
+  // Callsign sent as an FSK ID after the image
+  pub fsk_id: Option<String>,
+}
+
+// Position of the decoder within the image
+pub(crate) struct LineCursor {
+  pub line: usize,
+  pub seq_start: usize,
+  // Start of the most recently aligned sync pulse
+  pub sync_start: usize,
+  // Line timing to use in place of aligning each sync pulse
+  pub slant: Option<SlantCorrection>,
+  // Receiver mistuning, updated from each sync pulse to follow drift
+  pub freq_offset: f32,
+  // Whether the last R36 chroma scan decoded was B-Y
+  pub chroma_cb: bool,
 }
 
 
 // Create an SSTV decoder for decoding audio data
 impl SSTVSetup {
-  pub fn new(audio_file: &String) -> Self {
+  pub fn new(audio_file: &str) -> Result<Self, SSTVError> {
     use std::fs::File;
     use std::io::BufReader;
     use rodio::{Decoder, source::Source};
 
     // Load a sound from a file, using a path relative to Cargo.toml
-    let file = BufReader::new(File::open(audio_file).unwrap());
+    let file = BufReader::new(File::open(audio_file)?);
     // Decode that sound file into a source
-    let source = Decoder::new(file).unwrap();
+    let source = Decoder::new(file).map_err(|e| SSTVError::AudioFormat(e.to_string()))?;
 
     let sample_rate = source.sample_rate();
     let channels = source.channels();
+    if channels == 0 || sample_rate == 0 {
+      return Err(SSTVError::AudioFormat("audio has no channels".to_string()));
+    }
 
     // convert to mono if stereo
     let samples: Vec<i16> = source.step_by(channels as usize).collect();
 
+    Ok(SSTVSetup::from_samples(samples, sample_rate))
+  }
+
+  pub fn from_samples(samples: Vec<i16>, sample_rate: u32) -> Self {
+    // Use mono audio that has already been loaded
+    let track = demod::demodulate(&samples, sample_rate);
     SSTVSetup {
       sample_rate,
       samples,
+      track,
     }
   }
 
+  pub(crate) fn from_track(track: Vec<f32>, sample_rate: u32) -> Self {
+    SSTVSetup {
+      sample_rate,
+      samples: Vec::new(),
+      track,
+    }
+  }
 
-  pub fn decode(&self) -> Result<SSTVDecoder, String> {
+
+  pub fn sample_rate(&self) -> u32 {
+    self.sample_rate
+  }
+
+
+  pub fn decode(&self) -> Result<SSTVDecoder, SSTVError> {
     //"""Attempts to decode the audio data as an SSTV signal
     //Returns a PIL image on success, and None if no SSTV signal was found
     //"""
+    self.decode_from(0)
+  }
 
-    let header_end = self.find_header()?;
-    let mode = self.decode_vis(header_end)?;
 
-    let samples_copy: Vec<i16> = self.samples.iter().map(|e| *e).collect();
-    let new_s = SSTVDecoder {
-      mode,
-      sample_rate: self.sample_rate,
-      samples: samples_copy,
-      header_end,
-    };
+  pub fn decode_from(&self, start: usize) -> Result<SSTVDecoder, SSTVError> {
+    // Like decode, but only looks for a header from the given sample onwards
+    let header_end = self.find_header_from(start)?;
+    let freq_offset = self.measure_offset(header_end);
+    let mode = self.decode_vis(header_end, freq_offset)?;
+
+    Ok(SSTVDecoder::new(mode, self.sample_rate, self.track.clone(), header_end, freq_offset))
+  }
+
+
+  pub fn decode_as(&self, mode: spec::Spec, start: usize) -> Result<SSTVDecoder, SSTVError> {
+    //"""Decodes the first transmission from the given sample onwards as the
+    //given mode, whatever its VIS says"""
+    let header_end = self.find_header_from(start)?;
+    let freq_offset = self.measure_offset(header_end);
+
+    Ok(SSTVDecoder::new(mode, self.sample_rate, self.track.clone(), header_end, freq_offset))
+  }
+
+
+  pub fn decode_without_header(&self, mode: spec::Spec, start: usize) -> Result<SSTVDecoder, SSTVError> {
+    //"""Decodes as the given mode from the first sync pulse at or after the
+    //given sample, for transmissions whose header was missed or damaged.
+    //The image is filled from the top with whichever line was found first"""
+    let sync_start = self.find_sync(&mode, start).ok_or(SSTVError::SyncNotFound)?;
+
+    // Without a header the tuning can only be measured from the sync itself
+    let pulse = (mode.SYNC_PULSE * self.sample_rate as f32) as usize;
+    let freq_offset = mean_freq(&self.track[sync_start + pulse / 4..sync_start + pulse - pulse / 4]) - SYNC_FREQ;
+
+    Ok(SSTVDecoder::starting_at(mode, self.sample_rate, self.track.clone(), sync_start, freq_offset))
+  }
+
+
+  pub fn classify(&self, start: usize) -> Vec<ModeCandidate> {
+    //"""Ranks the known modes by how well they match the width and spacing
+    //of the sync pulses following the given sample"""
+    let start = std::cmp::min(start, self.track.len());
+    let end = std::cmp::min(self.track.len(), start + (CLASSIFY_TIME * self.sample_rate as f32) as usize);
+    let pulses = classify::find_pulses(&self.track[start..end], self.sample_rate, SYNC_THRESHOLD);
+    let mut candidates = classify::rank_modes(&pulses, self.sample_rate);
+    classify::break_ties(&mut candidates, &self.track[start..], self.sample_rate, SYNC_THRESHOLD);
+    candidates
+  }
+
+
+  fn find_sync(&self, mode: &spec::Spec, start: usize) -> Option<usize> {
+    //"""Finds the start of the first sync pulse of the mode at or after the
+    //given sample"""
+    let pulse = (mode.SYNC_PULSE * self.sample_rate as f32) as usize;
+    if pulse == 0 || start + pulse > self.track.len() {
+      return None;
+    }
+
+    // Nearly all of a pulse's length must sit below the threshold, which
+    // image content never does
+    let in_sync = |freq: f32| (freq < SYNC_THRESHOLD) as usize;
+    let needed = pulse * 9 / 10;
+    let mut count: usize = self.track[start..start + pulse].iter().map(|f| in_sync(*f)).sum();
+
+    for current_sample in start..self.track.len() - pulse {
+      if count >= needed {
+        return Some(current_sample);
+      }
+      count += in_sync(self.track[current_sample + pulse]);
+      count -= in_sync(self.track[current_sample]);
+    }
+    None
+  }
+
+
+  pub fn header_info(&self) -> Result<HeaderInfo, SSTVError> {
+    self.header_info_from(0)
+  }
+
+
+  pub fn header_info_from(&self, search_start: usize) -> Result<HeaderInfo, SSTVError> {
+    //"""Reads the calibration header and VIS of the first transmission
+    //after search_start without decoding the image"""
+    let header_end = self.find_header_from(search_start)?;
+    let header_start = header_end.saturating_sub((spec::HDR_SIZE * self.sample_rate as f32) as usize);
+    let freq_offset = self.measure_offset(header_end);
+    let vis = self.read_vis(header_end, freq_offset)?;
+    let snr = self.leader_snr(header_start, freq_offset);
+
+    Ok(HeaderInfo {
+      start: header_start,
+      end: header_end,
+      vis,
+      mode: spec::VIS_MAP(vis).ok(),
+      freq_offset,
+      snr,
+    })
+  }
+
 
-    Ok(new_s)
+  fn leader_snr(&self, header_start: usize, freq_offset: f32) -> Option<f32> {
+    // Needs the audio samples, which a setup made from a track doesn't have
+    if self.samples.is_empty() {
+      return None;
+    }
+    // Leader tone, clear of its edges
+    let guard = (spec::HDR_WINDOW_SIZE * self.sample_rate as f32) as usize;
+    let leader_end = header_start + (spec::BREAK_OFFSET * self.sample_rate as f32) as usize;
+    let leader = &self.samples[header_start + guard..leader_end - guard];
+    Some(demod::tone_snr(leader, self.sample_rate, LEADER_FREQ + freq_offset))
   }
 
 
+  pub fn decode_all(&self) -> Vec<Result<DecodedImage, SSTVError>> {
+    //"""Decodes every transmission in the audio, carrying on the search for
+    //a header after the end of each image. A transmission that can't be
+    //decoded gives its error in place of the image"""
+    let sample_rate = self.sample_rate as f32;
+    let header_size = (spec::HDR_SIZE * sample_rate) as usize;
+    let mut images: Vec<Result<DecodedImage, SSTVError>> = Vec::new();
+    let mut search_start = 0;
+
+    while let Ok(header_end) = self.find_header_from(search_start) {
+      let freq_offset = self.measure_offset(header_end);
+      let mode = match self.decode_vis(header_end, freq_offset) {
+        Ok(mode) => mode,
+        Err(err) => {
+          images.push(Err(err));
+          search_start = header_end;
+          continue;
+        },
+      };
+
+      // Only hand the decoder the audio this image and its ID can cover
+      let image_len = ((spec::vis_time(&mode) + mode.image_time()) * IMAGE_END_MARGIN + fskid::max_id_time()) * sample_rate;
+      let track_end = std::cmp::min(self.track.len(), header_end + image_len as usize);
+      let track = self.track[header_end..track_end].to_vec();
+
+      let decoder = SSTVDecoder::new(mode.clone(), self.sample_rate, track, 0, freq_offset);
+      let end = header_end + decoder.image_end();
+      search_start = end;
+
+      match decoder.decode_image() {
+        Ok(image) => {
+          let start = header_end.saturating_sub(header_size);
+          images.push(Ok(DecodedImage {
+            image,
+            mode,
+            start,
+            end,
+            timestamp: Duration::from_secs_f64(start as f64 / self.sample_rate as f64),
+            snr: self.leader_snr(start, freq_offset),
+            slant: decoder.slant(),
+            freq_offset,
+            final_freq_offset: decoder.final_freq_offset().unwrap_or(freq_offset),
+            fsk_id: decoder.fsk_id(),
+          }));
+        },
+        Err(err) => images.push(Err(err)),
+      }
+    }
+
+    images
+  }
+
 
-  fn find_header(&self) -> Result<usize, String> {
+
+  pub(crate) fn find_header(&self) -> Result<usize, SSTVError> {
     //"""Finds the approx sample of the end of the calibration header"""
+    self.find_header_from(0)
+  }
+
+
+  pub(crate) fn find_header_from(&self, search_start: usize) -> Result<usize, SSTVError> {
+    //"""Finds the approx sample of the end of the first calibration header
+    //starting at or after the given sample"""
 
     let header_size = (spec::HDR_SIZE * self.sample_rate as f32) as usize;
     let window_size = (
@@ -111,8 +354,12 @@ impl SSTVSetup {
     let leader_1_sample = 0;
     let leader_1_search = leader_1_sample + window_size;
 
-    let break_sample = (spec::BREAK_OFFSET * self.sample_rate as f32) as usize;
-    let break_search = break_sample + window_size;
+    let jump_size = (0.002 * self.sample_rate as f32) as usize;  // check every 2ms
+
+    // The break is only as long as the window, so only its middle is checked
+    // to still match when the search steps past its start
+    let break_sample = (spec::BREAK_OFFSET * self.sample_rate as f32) as usize + jump_size;
+    let break_search = break_sample + window_size.saturating_sub(2 * jump_size).max(1);
 
     let leader_2_sample = (spec::LEADER_OFFSET * self.sample_rate as f32) as usize;
     let leader_2_search = leader_2_sample + window_size;
@@ -120,16 +367,13 @@ impl SSTVSetup {
     let vis_start_sample = (spec::VIS_START_OFFSET * self.sample_rate as f32) as usize;
     let vis_start_search = vis_start_sample + window_size;
 
-    let jump_size = (0.002 * self.sample_rate as f32) as usize;  // check every 2ms
-
     // The margin of error created here will be negligible when decoding the
     // vis due to each bit having a length of 30ms. We fix this error margin
     // when decoding the image by aligning each sync pulse
 
-    let size = self.samples.iter().count();
+    let size = self.track.len();
 
-    for i in 0..(size - header_size) {
-      let current_sample = i * jump_size as usize;
+    for current_sample in (search_start..size.saturating_sub(header_size)).step_by(jump_size.max(1)) {
       // Update search progress message
       if current_sample % (jump_size * 256) == 0 {
         // search_msg = "Searching for calibration header... {:.1f}s";
@@ -138,281 +382,618 @@ impl SSTVSetup {
       }
 
       let search_end = current_sample + header_size;
-      let search_area = &self.samples[current_sample..search_end];
+      let search_area = &self.track[current_sample..search_end];
 
       let leader_1_area = &search_area[leader_1_sample..leader_1_search];
       let break_area = &search_area[break_sample..break_search];
       let leader_2_area = &search_area[leader_2_sample..leader_2_search];
       let vis_start_area = &search_area[vis_start_sample..vis_start_search];
 
-      // Check they're the correct frequencies
-      if (peak_fft_freq(leader_1_area, self.sample_rate) - 1900.0).abs() < 50.0
-          && (peak_fft_freq(break_area, self.sample_rate) - 1200.0).abs() < 50.0
-          && (peak_fft_freq(leader_2_area, self.sample_rate) - 1900.0).abs() < 50.0
-          && (peak_fft_freq(vis_start_area, self.sample_rate) - 1200.0).abs() < 50.0 {
+      // Check the tones are the correct distance apart, allowing for the
+      // whole header to be shifted by a mistuned receiver
+      let leader_1 = mean_freq(leader_1_area);
+      let leader_2 = mean_freq(leader_2_area);
+      let break_freq = mean_freq(break_area);
+      let vis_start = mean_freq(vis_start_area);
+      let offset = (leader_1 + leader_2) / 2.0 - LEADER_FREQ;
+
+      if offset.abs() < MAX_FREQ_OFFSET
+          && (leader_1 - leader_2).abs() < 50.0
+          && (break_freq - SYNC_FREQ - offset).abs() < 50.0
+          && (vis_start - SYNC_FREQ - offset).abs() < 50.0 {
 
         return Ok(current_sample + header_size);
       }
     }
 
-    return Err("Couldn't find SSTV header in the given audio file".to_string());
+    Err(SSTVError::HeaderNotFound)
+  }
+
+  pub(crate) fn measure_offset(&self, header_end: usize) -> f32 {
+    //"""Measures how far the receiver is mistuned from the two leader tones
+    //of the header ending at the given sample"""
+    let sample_rate = self.sample_rate as f32;
+    let header_start = header_end.saturating_sub((spec::HDR_SIZE * sample_rate) as usize);
+    // Stay clear of the tone edges, which are only known to within a few ms
+    let guard = (spec::HDR_WINDOW_SIZE * sample_rate) as usize;
+
+    let leader_1_start = header_start + guard;
+    let leader_1_end = header_start + (spec::BREAK_OFFSET * sample_rate) as usize - guard;
+    let leader_2_start = header_start + (spec::LEADER_OFFSET * sample_rate) as usize + guard;
+    let leader_2_end = header_start + (spec::VIS_START_OFFSET * sample_rate) as usize - guard;
+
+    let leader_1 = mean_freq(&self.track[leader_1_start..leader_1_end]);
+    let leader_2 = mean_freq(&self.track[leader_2_start..leader_2_end]);
+    (leader_1 + leader_2) / 2.0 - LEADER_FREQ
   }
 
-  fn decode_vis(&self, vis_start: usize) -> Result<spec::Spec, String> {
+
+  pub(crate) fn decode_vis(&self, vis_start: usize, freq_offset: f32) -> Result<spec::Spec, SSTVError> {
       //"""Decodes the vis from the audio data and returns the SSTV mode"""
+      let vis_value = self.read_vis(vis_start, freq_offset)?;
 
-      let bit_size = (spec::VIS_BIT_SIZE * self.sample_rate as f32) as usize;
-      let mut vis_bits: Vec<usize> = Vec::new();
+      spec::VIS_MAP(vis_value).map_err(|_| SSTVError::UnsupportedVis(vis_value))
+  }
 
-      for bit_idx in 0..8 {
-        let bit_offset = vis_start + bit_idx * bit_size;
-        let window_width = bit_offset + bit_size;
-        let section = &self.samples[bit_offset..window_width];
-        let freq = peak_fft_freq(section, self.sample_rate);
-        // 1100 hz = 1, 1300hz = 0
-        // println!("Bit {}: {}", bit_idx, freq);
-        if freq <= 1200.0 {
-          vis_bits.push(1)
-        } else {
-          vis_bits.push(0)
+  pub(crate) fn read_vis(&self, vis_start: usize, freq_offset: f32) -> Result<usize, SSTVError> {
+      //"""Reads the VIS code following the calibration header. MMSSTV's
+      //extended VIS is returned as its two bytes, first byte lowest"""
+      let vis_byte = self.read_vis_byte(vis_start, freq_offset)?;
+
+      if vis_byte == spec::EXT_VIS_PREFIX {
+        // The second byte of an extended VIS has odd parity, like the
+        // prefix itself
+        let bit_size = (spec::VIS_BIT_SIZE * self.sample_rate as f32) as usize;
+        let ext_byte = self.read_vis_byte(vis_start + 8 * bit_size, freq_offset)?;
+        if ext_byte.count_ones() % 2 == 0 {
+          return Err(SSTVError::VisParity);
         }
+        return Ok((ext_byte << 8) | vis_byte);
       }
 
       // Check for even parity in last bit
-      let vis_bit_sum: usize = vis_bits.iter().sum();
-      let parity = vis_bit_sum % 2 == 0;
-      if !parity {
-        return Err("Error decoding VIS header (invalid parity bit)".to_string());
+      if vis_byte.count_ones() % 2 != 0 {
+        return Err(SSTVError::VisParity);
       }
+      // Ignore the parity bit
+      Ok(vis_byte & 0x7F)
+  }
+
+  fn read_vis_byte(&self, byte_start: usize, freq_offset: f32) -> Result<usize, SSTVError> {
+      //"""Reads 8 VIS bits, sent LSB first, including any parity bit"""
 
-      vis_bits.pop();
-      vis_bits.reverse();
-      // println!("vis bits: {:?}", vis_bits);
-      // LSB first so we must reverse and ignore the parity bit
-      let mut vis_value = 0;
-      for bit in vis_bits {
-        vis_value = (vis_value << 1) | bit;
+      let bit_size = (spec::VIS_BIT_SIZE * self.sample_rate as f32) as usize;
+
+      if byte_start + 8 * bit_size > self.track.len() {
+        return Err(SSTVError::TruncatedVis);
       }
 
-      match spec::VIS_MAP(vis_value) {
-        Ok(mode) => {
-          println!("Detected SSTV mode {}", mode.NAME);
-          return Ok(mode);
+      let mut vis_byte = 0;
+      for bit_idx in 0..8 {
+        let bit_offset = byte_start + bit_idx * bit_size;
+        let window_width = bit_offset + bit_size;
+        let section = &self.track[bit_offset..window_width];
+        let freq = mean_freq(section);
+        // 1100 hz = 1, 1300hz = 0
+        if freq <= SYNC_FREQ + freq_offset {
+          vis_byte |= 1 << bit_idx;
         }
-        Err(_) => Err(format!("SSTV mode is unsupported (VIS: {})", vis_value))
       }
+      Ok(vis_byte)
   }
 }
 
 impl SSTVDecoder {
+  pub(crate) fn new(mode: spec::Spec, sample_rate: u32, track: Vec<f32>, header_end: usize, freq_offset: f32) -> Self {
+    // Image follows the VIS stop bit
+    let image_start = (header_end as f32 + (spec::vis_time(&mode) * sample_rate as f32)) as usize;
+    SSTVDecoder::starting_at(mode, sample_rate, track, image_start, freq_offset)
+  }
+
+
+  fn starting_at(mode: spec::Spec, sample_rate: u32, track: Vec<f32>, image_start: usize, freq_offset: f32) -> Self {
+    SSTVDecoder {
+      mode,
+      sample_rate,
+      track,
+      image_start,
+      freq_offset,
+      color_range: ColorRange::Full,
+      slant_correction: true,
+      slant: Cell::new(None),
+      final_freq_offset: Cell::new(None),
+    }
+  }
+
+
   #[allow(unused)]
   #[deprecated(since="0.1.0", note="please use `new_method` instead")]
-  pub fn save(&self, filename: &str) -> Result<(), String> {
-    let vis_end = (self.header_end as f32 + (spec::VIS_BIT_SIZE * 9.0 * self.sample_rate as f32)) as usize;
-
-    let image_data: PixelVec = self.decode_image_data(vis_end)?;
-    let img: img::Image = self.draw_image(image_data);
-    match img.write_file(filename) {
-      Err(..) => Err("Encounter error when writing to file".to_string()),
-      Ok(..) => {
-        println!("File written");
-        Ok(())
-      }
+  pub fn save(&self, filename: &str) -> Result<(), SSTVError> {
+    let image_data: PixelVec = self.decode_image_data(self.image_start(), None, |_, _| ())?;
+    let img: img::Image = self.draw_image(&image_data);
+    img.write_file(filename)?;
+    Ok(())
+  }
+
+
+  pub fn mode(&self) -> &spec::Spec {
+    &self.mode
+  }
+
+
+  pub fn image_start_time(&self) -> Duration {
+    // Time of the first image line from the start of the recording
+    Duration::from_secs_f64(self.image_start as f64 / self.sample_rate as f64)
+  }
+
+
+  pub fn freq_offset(&self) -> f32 {
+    // Receiver mistuning in Hz measured from the calibration header
+    self.freq_offset
+  }
+
+
+  pub fn set_color_range(&mut self, range: ColorRange) {
+    // Range of the YCbCr values sent by Robot modes
+    self.color_range = range;
+  }
+
+
+  pub fn set_slant_correction(&mut self, enabled: bool) {
+    // Whether decode_image re-times the image to the measured sample rate
+    self.slant_correction = enabled;
+  }
+
+
+  pub fn final_freq_offset(&self) -> Option<f32> {
+    // Offset followed to the last line by the most recent decode
+    self.final_freq_offset.get()
+  }
+
+
+  pub fn slant(&self) -> Option<SlantCorrection> {
+    // Slant corrected by the last decode_image, if any was found
+    self.slant.get()
+  }
+
+
+  pub fn decode_image(&self) -> Result<img::Image, SSTVError> {
+    self.decode_image_rows(|_, _| ())
+  }
+
+
+  pub fn decode_image_rows<F: FnMut(usize, &[u8])>(&self, on_row: F) -> Result<img::Image, SSTVError> {
+    //"""Decodes the image, passing each row's packed RGB pixels to on_row
+    //as soon as the lines it's drawn from have been decoded"""
+    let mut slant = None;
+    if self.slant_correction {
+      slant = self.measure_slant()?;
     }
+    self.slant.set(slant);
+
+    let image_data: PixelVec = self.decode_image_data(self.image_start(), slant, on_row)?;
+    Ok(self.draw_image(&image_data))
   }
 
 
-  pub fn save_png(&self, filename: &str) -> Result<(), String> {
-    let vis_end = (self.header_end as f32 + (spec::VIS_BIT_SIZE * 9.0 * self.sample_rate as f32)) as usize;
+  pub fn measure_slant(&self) -> Result<Option<SlantCorrection>, SSTVError> {
+    //"""Estimates the real sample rate of the recording by fitting a line
+    //through the sync pulses of the image. Only the sync pulses are read,
+    //the pixels between them are skipped"""
+    let sample_rate = self.sample_rate as f32;
+    let mut cursor = self.start_image(self.image_start())?;
+    let mut syncs: Vec<(usize, usize)> = Vec::new();
 
-    let image_data: PixelVec = self.decode_image_data(vis_end)?;
-    let img: img::Image = self.draw_image(image_data);
-    match img.write_file_png(filename) {
-      Err(..) => Err("Encounter error when writing to file".to_string()),
-      Ok(..) => {
-        println!("File written");
-        Ok(())
+    while cursor.line < self.mode.sync_lines() {
+      self.rewind_first_line(&mut cursor, sample_rate);
+      if !self.next_sync(&mut cursor, sample_rate) {
+        break;
       }
+      if self.has_sync(&cursor) {
+        syncs.push((cursor.line, cursor.sync_start));
+      }
+      cursor.line += 1;
     }
+
+    let nominal_line = self.mode.LINE_TIME as f64 * self.sample_rate as f64;
+    // A sync found by align_sync can be off by up to half its length
+    let tolerance = self.mode.SYNC_PULSE as f64 * self.sample_rate as f64 / 2.0;
+    Ok(slant::fit_syncs(&syncs, nominal_line, self.sample_rate, tolerance))
+  }
+
+
+  pub fn fsk_id(&self) -> Option<String> {
+    //"""Callsign of an FSK ID sent just after the image, if there is one"""
+    let after = self.track.get(self.image_end()..)?;
+    fskid::find_fsk_id(after, self.sample_rate, self.freq_offset).map(|(callsign, _)| callsign)
+  }
+
+
+  pub fn save_png(&self, filename: &str) -> Result<(), SSTVError> {
+    let img: img::Image = self.decode_image()?;
+    img.write_file_png(filename)?;
+    Ok(())
   }
 
 
-  fn align_sync(&self, align_start: usize, start_of_sync:bool) -> Result<usize,()> {
+  fn align_sync(&self, align_start: usize, start_of_sync:bool, freq_offset: f32) -> Option<usize> {
     // """Returns sample where the beginning of the sync pulse was found"""
 
     // TODO - improve this
 
     let sync_window = (self.mode.SYNC_PULSE * 1.4 * self.sample_rate as f32) as usize;
-    let align_stop = self.samples.len() - sync_window;
+    let align_stop = self.track.len().saturating_sub(sync_window);
 
     if align_stop <= align_start {
-      return Err(());  // Reached end of audio
+      return None;  // Reached end of audio
     }
 
     let mut curr_sample_ref = align_stop;
 
-    for current_sample in align_start..align_stop {
-      let section_end = current_sample + sync_window;
-      let search_section = &self.samples[current_sample..section_end];
+    // Slide the window along the track until most of it is above the sync
+    // frequency, keeping a running count of those samples
+    let above_sync = |freq: f32| (freq > SYNC_THRESHOLD + freq_offset) as usize;
+    let mut above_count: usize = self.track[align_start..align_start + sync_window]
+      .iter().map(|f| above_sync(*f)).sum();
 
-      if peak_fft_freq(search_section, self.sample_rate) > 1350.0 {
+    for current_sample in align_start..align_stop {
+      if 2 * above_count > sync_window {
         curr_sample_ref = current_sample;
         break;
       }
+      above_count += above_sync(self.track[current_sample + sync_window]);
+      above_count -= above_sync(self.track[current_sample]);
     }
 
-    let end_sync = curr_sample_ref as f32 + (sync_window  as f32/ 2.0) as f32;
+    let end_sync = curr_sample_ref as f32 + (sync_window as f32 / 2.0);
 
     if start_of_sync {
-      return Ok((end_sync as f32 - (self.mode.SYNC_PULSE * self.sample_rate as f32)) as usize);
+      Some((end_sync - (self.mode.SYNC_PULSE * self.sample_rate as f32)) as usize)
     } else {
-      return Ok(end_sync as usize);
+      Some(end_sync as usize)
     }
   }
 
-  fn decode_image_data(&self, image_start: usize) -> Result<PixelVec, String> {
-      // """Decodes image from the transmission section of an sstv signal"""
+  pub(crate) fn image_start(&self) -> usize {
+    self.image_start
+  }
 
-      let window_factor = self.mode.WINDOW_FACTOR;
-      let centre_window_time = (self.mode.PIXEL_TIME * window_factor) / 2.0;
-      let mut pixel_window = (centre_window_time * 2.0 * self.sample_rate as f32) as usize;
 
-      let height = self.mode.LINE_COUNT;
-      let channels = self.mode.CHAN_COUNT;
-      let width = self.mode.LINE_WIDTH;
-      // Use list comprehension to init list so we can return data early
-      let mut image_data: PixelVec = vec![vec![vec![0; width]; channels]; height];
+  pub(crate) fn image_end(&self) -> usize {
+    // Nominal sample after the last line of the image
+    let end = self.image_start() + (self.mode.image_time() * self.sample_rate as f32) as usize;
+    std::cmp::min(end, self.track.len())
+  }
 
 
-      let mut seq_start = image_start;
-      if self.mode.HAS_START_SYNC {
-        // Start at the end of the initial sync pulse
-        let res = self.align_sync(image_start, false);
-        match res {
-          Err(..) => return Err("Reached end of audio before image data".to_string()),
-          Ok(val) => seq_start = val,
+  pub(crate) fn new_image_data(&self) -> PixelVec {
+    // R36 keeps its R-Y and B-Y scans apart so each line can hold both
+    let channels = self.mode.CHAN_COUNT + self.mode.HAS_ALT_SCAN as usize;
+    vec![vec![vec![0; self.mode.LINE_WIDTH]; channels]; self.mode.sync_lines()]
+  }
+
+
+  pub(crate) fn start_image(&self, image_start: usize) -> Result<LineCursor, SSTVError> {
+    //"""Finds where the first line of the image begins"""
+    let mut seq_start = image_start;
+    if self.mode.HAS_START_SYNC {
+      // Start at the end of the initial sync pulse, without which not even
+      // the first line can be found
+      match self.align_sync(image_start, false, self.freq_offset) {
+        None => return Err(SSTVError::TruncatedAudio { line: 0 }),
+        Some(val) => seq_start = val,
+      }
+    }
+
+    Ok(LineCursor {
+      line: 0,
+      seq_start,
+      sync_start: seq_start,
+      slant: None,
+      freq_offset: self.freq_offset,
+      chroma_cb: false,
+    })
+  }
+
+
+  pub(crate) fn decode_line(&self, cursor: &mut LineCursor, image_data: &mut PixelVec) -> bool {
+    //"""Decodes the channels following the next sync pulse, returning false
+    //if the end of the audio was reached first"""
+
+    // Pixels are timed against the measured sample rate when correcting slant
+    let sample_rate = match &cursor.slant {
+      Some(fit) => fit.sample_rate as f32,
+      None => self.sample_rate as f32,
+    };
+
+    let window_factor = self.mode.WINDOW_FACTOR;
+    let centre_window_time = (self.mode.PIXEL_TIME * window_factor) / 2.0;
+    // High resolution modes have pixels only a sample or two long at low
+    // sample rates, and an empty window would read as black
+    let mut pixel_window = ((centre_window_time * 2.0 * sample_rate) as usize).max(1);
+
+    let channels = self.mode.CHAN_COUNT;
+    let width = self.mode.LINE_WIDTH;
+    let line = cursor.line;
+    let prev_cb = cursor.chroma_cb;
+
+    self.rewind_first_line(cursor, sample_rate);
+
+    for chan in 0..channels {
+      if chan == self.mode.CHAN_SYNC && !self.next_sync(cursor, sample_rate) {
+        return false;
+      }
+      let mut pixel_time = self.mode.PIXEL_TIME;
+      if self.mode.HAS_HALF_SCAN {
+        // Robot mode has half-length second/third scans
+        if chan > 0 {
+          pixel_time = self.mode.HALF_PIXEL_TIME;
         }
+
+        let centre_window_time = (pixel_time * window_factor) / 2.0;
+        pixel_window = ((centre_window_time * 2.0 * sample_rate) as usize).max(1);
       }
 
-      for line in 0..height {
-        if self.mode.CHAN_SYNC > 0 && line == 0 {
-          // Align seq_start to the beginning of the previous sync pulse
-          let sync_offset = self.mode.CHAN_OFFSETS[self.mode.CHAN_SYNC];
-          seq_start -=((sync_offset + self.mode.SCAN_TIME) * self.sample_rate as f32) as usize;
+      let mut plane = chan;
+      if self.mode.HAS_ALT_SCAN && chan == 1 {
+        // Line parity is unknown when joining an image part way through,
+        // so the separator tone decides which chroma scan this is
+        match self.separator_is_cb(cursor, sample_rate) {
+          None => return false,
+          Some(cb) => {
+            cursor.chroma_cb = cb;
+            plane = if cb { 2 } else { 1 };
+          },
         }
+      }
 
-        for chan in 0..channels {
-          if chan == self.mode.CHAN_SYNC {
-            if line > 0 || chan > 0 {
-              // Set base offset to the next line
-              seq_start += (self.mode.LINE_TIME * self.sample_rate as f32) as usize;
-            }
-            // Align to start of sync pulse
-            let res = self.align_sync(seq_start, true);
-            match res {
-              Err(..) => {
-                println!("Reached end of audio whilst decoding.");
-                return Ok(image_data);
-              },
-              Ok(start) => seq_start = start,
-            }
-          }
-          let mut pixel_time = self.mode.PIXEL_TIME;
-          if self.mode.HAS_HALF_SCAN {
-            // Robot mode has half-length second/third scans
-            if chan > 0 {
-              pixel_time = self.mode.HALF_PIXEL_TIME;
-            }
-
-            let centre_window_time = (pixel_time * window_factor) / 2.0;
-            pixel_window = (centre_window_time * 2.0 * self.sample_rate as f32) as usize;
-          }
-
-          for px in 0..width {
-            let chan_offset = self.mode.CHAN_OFFSETS[chan];
-
-            let px_pos = (seq_start as f32 + (chan_offset + px as f32 *
-                            pixel_time - centre_window_time) *
-                            self.sample_rate as f32) as usize;
-            let px_end = px_pos + pixel_window;
-
-            // If we are performing fft past audio length, stop early
-            if px_end >= self.samples.len() {
-              println!("Reached end of audio whilst decoding.");
-              return Ok(image_data);
-            }
-
-            let pixel_area = &self.samples[px_pos..px_end];
-            let freq = peak_fft_freq(pixel_area, self.sample_rate);
-
-            image_data[line][chan][px] = calc_lum(freq);
-          }
-
-          // progress_bar(line, height - 1, "Decoding image...");`
+      let chan_offset = self.mode.CHAN_OFFSETS[chan];
+      for (px, value) in image_data[line][plane].iter_mut().enumerate().take(width) {
+        let px_pos = (cursor.seq_start as f32 + (chan_offset + px as f32 *
+                        pixel_time - centre_window_time) *
+                        sample_rate) as usize;
+        // The window of the last pixel can run past audio that ends with
+        // the image, so only stop early once a pixel has no audio at all
+        let px_end = std::cmp::min(px_pos + pixel_window, self.track.len());
+        if px_pos >= px_end {
+          return false;
         }
+
+        let pixel_area = &self.track[px_pos..px_end];
+        let freq = mean_freq(pixel_area);
+
+        *value = calc_lum(freq - cursor.freq_offset);
       }
-    return Ok(image_data);
+    }
+
+    if self.mode.HAS_ALT_SCAN {
+      self.share_chroma(line, prev_cb, cursor.chroma_cb, image_data);
+    }
+
+    cursor.line += 1;
+    true
   }
 
-  fn draw_image(&self, image_data: PixelVec) -> img::Image {
-    //"""Renders the image from the decoded sstv signal"""
-    // Let PIL do YUV-RGB conversion for us
-    if self.mode.COLOR == spec::ColFmt::YUV {
-      let col_mode: &str = "YCbCr";
+
+  fn rewind_first_line(&self, cursor: &mut LineCursor, sample_rate: f32) {
+    //"""Scottie modes send the first scans of a line before its sync pulse,
+    //so the first line starts that far ahead of the first sync"""
+    if self.mode.CHAN_SYNC == 0 || cursor.line > 0 {
+      return;
+    }
+    // Align seq_start to the beginning of the previous sync pulse
+    match &cursor.slant {
+      Some(fit) => cursor.seq_start = (fit.sync_start(0) - fit.line_samples).max(0.0) as usize,
+      None => {
+        let sync_offset = self.mode.CHAN_OFFSETS[self.mode.CHAN_SYNC];
+        let backtrack = ((sync_offset + self.mode.SCAN_TIME) * sample_rate) as usize;
+        cursor.seq_start = cursor.seq_start.saturating_sub(backtrack);
+      },
+    }
+  }
+
+
+  fn next_sync(&self, cursor: &mut LineCursor, sample_rate: f32) -> bool {
+    //"""Aligns the cursor to the sync pulse of its line, returning false if
+    //the end of the audio was reached first"""
+    let sync_start = match &cursor.slant {
+      // Place the sync where the fitted line timing puts it
+      Some(fit) => Some(fit.sync_start(cursor.line).max(0.0) as usize)
+        .filter(|start| *start < self.track.len()),
+      None => {
+        if cursor.line > 0 || self.mode.CHAN_SYNC > 0 {
+          // Set base offset to the next line
+          cursor.seq_start += (self.mode.LINE_TIME * sample_rate) as usize;
+        }
+        // Align to start of sync pulse
+        self.align_sync(cursor.seq_start, true, cursor.freq_offset)
+      },
+    };
+    match sync_start {
+      None => false,
+      Some(start) => {
+        cursor.seq_start = start;
+        cursor.sync_start = start;
+        self.track_offset(cursor);
+        true
+      },
+    }
+  }
+
+
+  fn separator_is_cb(&self, cursor: &LineCursor, sample_rate: f32) -> Option<bool> {
+    //"""Reads the separator before the chroma scan of a R36 line, which is
+    //1500 Hz before R-Y and 2300 Hz before B-Y"""
+    let sep_offset = self.mode.CHAN_OFFSETS[1] - self.mode.SEP_PORCH - self.mode.SEP_PULSE;
+    let sep_start = cursor.seq_start as f32 + sep_offset * sample_rate;
+    let sep_len = self.mode.SEP_PULSE * sample_rate;
+    // Only the middle of the pulse is clear of the filter's rise time
+    let start = (sep_start + sep_len / 4.0) as usize;
+    let end = (sep_start + sep_len * 0.75) as usize;
+    if end > self.track.len() || end <= start {
+      return None;
+    }
+    Some(mean_freq(&self.track[start..end]) > SEP_THRESHOLD + cursor.freq_offset)
+  }
+
+
+  fn share_chroma(&self, line: usize, prev_cb: bool, cb: bool, image_data: &mut PixelVec) {
+    //"""Copies the chroma scan of a R36 line to the other line of its pair,
+    //which is the one after an R-Y scan and the one before a B-Y scan"""
+    let last = self.mode.LINE_COUNT - 1;
+    if cb {
+      if line > 0 {
+        image_data[line - 1][2] = image_data[line][2].clone();
+      }
     } else {
-      let col_mode = "RGB";
+      if line < last {
+        image_data[line + 1][1] = image_data[line][1].clone();
+      }
+      if line == 1 && prev_cb {
+        // An image joined on a B-Y scan has no R-Y scan before it
+        image_data[0][1] = image_data[1][1].clone();
+      }
+      if line == last && line > 0 {
+        // An image ending on an R-Y scan has no B-Y scan of its own
+        image_data[line][2] = image_data[line - 1][2].clone();
+      }
+    }
+  }
+
+
+  pub(crate) fn has_sync(&self, cursor: &LineCursor) -> bool {
+    //"""Checks whether a sync pulse was actually sent where the last one
+    //was aligned"""
+    let sync_start = cursor.sync_start;
+    let sync_end = sync_start + (self.mode.SYNC_PULSE * self.sample_rate as f32) as usize;
+    if sync_end > self.track.len() {
+      return false;
     }
+    mean_freq(&self.track[sync_start..sync_end]) < SYNC_THRESHOLD + cursor.freq_offset
+  }
 
-    let width = self.mode.LINE_WIDTH;
-    let height = self.mode.LINE_COUNT;
-    let channels = self.mode.CHAN_COUNT;
 
-    let mut image = img::Image::new(height as u32, width as u32);
+  fn track_offset(&self, cursor: &mut LineCursor) {
+    //"""Nudges the frequency offset towards that of the sync pulse just
+    //aligned, so that drift during the image is followed"""
+    let pulse = (self.mode.SYNC_PULSE * self.sample_rate as f32) as usize;
+    // Only the middle of the pulse is clear of the filter's rise time
+    let start = cursor.sync_start + pulse / 4;
+    let end = cursor.sync_start + pulse - pulse / 4;
+    if end > self.track.len() || end <= start {
+      return;
+    }
 
-    println!("Drawing image data...");
+    let error = mean_freq(&self.track[start..end]) - SYNC_FREQ - cursor.freq_offset;
+    // Anything further out isn't a sync pulse
+    if error.abs() < SYNC_THRESHOLD - SYNC_FREQ {
+      cursor.freq_offset += AFC_TRACK_RATE * error;
+    }
+  }
 
-    for y in 0..height {
-      let odd_line = y % 2;
-      for x in 0..width {
-        let mut pixel = (0,0,0);
-        if channels == 2 {
-
-          if self.mode.HAS_ALT_SCAN {
-            if self.mode.COLOR == spec::ColFmt::YUV {
-              // R36
-              pixel = (image_data[y][0][x],
-                        image_data[y-(odd_line-1)][1][x],
-                        image_data[y-odd_line][1][x]);
-            }
-          }
-
-        } else if channels == 3 {
-          if self.mode.COLOR == spec::ColFmt::GBR {
-            // M1, M2, S1, S2, SDX
-            pixel = (image_data[y][2][x],
-                      image_data[y][0][x],
-                      image_data[y][1][x]);
-          } else if self.mode.COLOR == spec::ColFmt::YUV {
-            // R72
-            pixel = (image_data[y][0][x],
-                          image_data[y][2][x],
-                          image_data[y][1][x]);
-          } else if self.mode.COLOR == spec::ColFmt::RGB {
-            pixel = (image_data[y][0][x],
-                          image_data[y][1][x],
-                          image_data[y][2][x]);
-          }
 
+  fn decode_image_data<F: FnMut(usize, &[u8])>(&self, image_start: usize, slant: Option<SlantCorrection>, mut on_row: F) -> Result<PixelVec, SSTVError> {
+      // """Decodes image from the transmission section of an sstv signal"""
+
+      // Use list comprehension to init list so we can return data early
+      let mut image_data: PixelVec = self.new_image_data();
+      let mut cursor = self.start_image(image_start)?;
+      cursor.slant = slant;
+
+      while cursor.line < self.mode.sync_lines() {
+        if !self.decode_line(&mut cursor, &mut image_data) {
+          // Rows already passed to on_row are all that could be decoded
+          self.final_freq_offset.set(Some(cursor.freq_offset));
+          return Err(SSTVError::TruncatedAudio { line: cursor.line });
+        }
+
+        for y in self.completed_rows(cursor.line - 1) {
+          on_row(y, &self.draw_row(&image_data, y));
         }
-        image.set_pixel_usize(x as u32, y as u32, pixel);
       }
+
+      self.final_freq_offset.set(Some(cursor.freq_offset));
+    Ok(image_data)
+  }
+
+
+  pub(crate) fn completed_rows(&self, line: usize) -> std::ops::Range<usize> {
+    //"""Image rows that can be drawn once the given line has been decoded"""
+    if self.mode.HAS_DUAL_LINE || self.mode.HAS_DOUBLE_HEIGHT {
+      2 * line..2 * line + 2
+    } else if self.mode.HAS_ALT_SCAN {
+      // A R36 line takes its other chroma scan from one of its neighbours,
+      // so it's only complete once the next line has been decoded
+      let end = if line + 1 == self.mode.LINE_COUNT { line + 1 } else { line };
+      line.saturating_sub(1)..end
+    } else {
+      line..line + 1
     }
+  }
 
 
-    // if image.mode != RGB {
-    //   image = image.convert("RGB");
-    // }
+  pub(crate) fn draw_row(&self, image_data: &PixelVec, y: usize) -> Vec<u8> {
+    //"""Renders a single RGB row of the image from the decoded sstv signal"""
+
+    let width = self.mode.LINE_WIDTH;
+    let channels = self.mode.CHAN_COUNT;
+
+    // The scans holding the three components of the row, in the order
+    // to_rgb expects
+    let blank = vec![0; width];
+    let scan = |line: usize, chan: usize| image_data[line][chan].as_slice();
+    let planes: [&[usize]; 3] = if channels == 1 {
+      // Robot black and white modes only send Y
+      [scan(y, 0), &blank, &blank]
+
+    } else if channels == 2 && self.mode.HAS_ALT_SCAN && self.mode.COLOR == spec::ColFmt::YUV {
+      // R36 lines hold the Cr and Cb scans shared within their pair
+      [scan(y, 0), scan(y, 2), scan(y, 1)]
+
+    } else if channels == 3 {
+      // Martin 3/4, Scottie 3/4 and SC2-30 repeat each line
+      let y = if self.mode.HAS_DOUBLE_HEIGHT { y / 2 } else { y };
+      if self.mode.COLOR == spec::ColFmt::YUV {
+        // R72
+        [scan(y, 0), scan(y, 2), scan(y, 1)]
+      } else {
+        // Martin, Scottie and Wraase SC2 modes, put in RGB order by
+        // to_rgb
+        [scan(y, 0), scan(y, 1), scan(y, 2)]
+      }
+
+    } else if channels == 4 && self.mode.HAS_DUAL_LINE {
+      // PD modes share the chroma scans between a pair of lines
+      let pair = y / 2;
+      let lum_chan = if y % 2 == 1 { 3 } else { 0 };
+      [scan(pair, lum_chan), scan(pair, 2), scan(pair, 1)]
+
+    } else {
+      [&blank, &blank, &blank]
+    };
+
+    let mut row: Vec<u8> = Vec::with_capacity(3 * width);
+    for ((first, second), third) in planes[0].iter().zip(planes[1]).zip(planes[2]).take(width) {
+      let pixel = (*first as u8, *second as u8, *third as u8);
+      let (r, g, b) = color::to_rgb(&self.mode.COLOR, pixel, self.color_range);
+      row.extend([r, g, b]);
+    }
+
+    row
+  }
+
+
+  pub(crate) fn draw_image(&self, image_data: &PixelVec) -> img::Image {
+    //"""Renders the image from the decoded sstv signal"""
+
+    let height = self.mode.LINE_COUNT;
+    let mut image = img::Image::new(height as u32, self.mode.LINE_WIDTH as u32);
+    image.set_greyscale(self.mode.COLOR == spec::ColFmt::BW);
+
+    for y in 0..height {
+      image.set_row(y as u32, &self.draw_row(image_data, y));
+    }
 
     image
   }
-}
\ No newline at end of file
+}
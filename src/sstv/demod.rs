@@ -0,0 +1,160 @@
+// Quadrature FM discriminator producing the instantaneous frequency of
+// every audio sample
+
+use std::f64::consts::PI;
+
+
+// Centre of the 1100-2300 Hz band used by SSTV
+const CENTRE_FREQ: f32 = 1700.0;
+// Low-pass filter for the mixed down signal, which must reject the image
+// of the band sitting at -2800 Hz and below
+const CUTOFF_FREQ: f32 = 1300.0;
+const TRANSITION_WIDTH: f32 = 1500.0;
+
+
+fn low_pass_taps(sample_rate: u32) -> Vec<f32> {
+  //"""Designs a Hamming windowed-sinc low-pass filter"""
+  let sample_rate = sample_rate as f32;
+  // Odd length so the filter delay is a whole number of samples
+  let length = (3.3 * sample_rate / TRANSITION_WIDTH) as usize | 1;
+
+  let centre = (length / 2) as f32;
+  let cutoff = CUTOFF_FREQ / sample_rate;
+
+  let taps: Vec<f32> = (0..length).map(|n| {
+    let x = n as f32 - centre;
+    let sinc = if x == 0.0 {
+      2.0 * cutoff
+    } else {
+      (2.0 * std::f32::consts::PI * cutoff * x).sin() / (std::f32::consts::PI * x)
+    };
+    let window = 0.54 - 0.46 * (2.0 * std::f32::consts::PI * n as f32 / (length - 1) as f32).cos();
+    sinc * window
+  }).collect();
+
+  // Normalise to unity gain at DC
+  let gain: f32 = taps.iter().sum();
+  taps.iter().map(|t| t / gain).collect()
+}
+
+
+pub struct FmDemodulator {
+  sample_rate: u32,
+  taps: Vec<f32>,
+  // Mixed samples, stored twice over so a full window is always contiguous
+  history: Vec<(f32, f32)>,
+  pos: usize,
+  phase: f64,
+  prev: (f32, f32),
+}
+
+impl FmDemodulator {
+  pub fn new(sample_rate: u32) -> FmDemodulator {
+    let taps = low_pass_taps(sample_rate);
+    let history = vec![(0.0, 0.0); 2 * taps.len()];
+    FmDemodulator {
+      sample_rate,
+      taps,
+      history,
+      pos: 0,
+      phase: 0.0,
+      prev: (0.0, 0.0),
+    }
+  }
+
+  pub fn delay(&self) -> usize {
+    // Group delay of the low-pass filter in samples
+    self.taps.len() / 2
+  }
+
+  pub fn process(&mut self, samples: &[i16]) -> Vec<f32> {
+    //"""Returns the instantaneous frequency of each sample, delayed by the
+    //group delay of the filter"""
+    let n = self.taps.len();
+    let step = 2.0 * PI * CENTRE_FREQ as f64 / self.sample_rate as f64;
+    let hz_per_radian = self.sample_rate as f32 / (2.0 * std::f32::consts::PI);
+    let mut freqs = Vec::with_capacity(samples.len());
+
+    for sample in samples {
+      // Mix the band down around 0 Hz
+      let x = *sample as f32;
+      let (sin, cos) = self.phase.sin_cos();
+      self.phase = (self.phase + step) % (2.0 * PI);
+      let mixed = (x * cos as f32, -x * sin as f32);
+
+      self.history[self.pos] = mixed;
+      self.history[self.pos + n] = mixed;
+      self.pos = (self.pos + 1) % n;
+
+      let window = &self.history[self.pos..self.pos + n];
+      let (mut i, mut q) = (0.0, 0.0);
+      for (tap, (a, b)) in self.taps.iter().zip(window) {
+        i += tap * a;
+        q += tap * b;
+      }
+
+      // Phase change since the previous sample
+      let (pi, pq) = self.prev;
+      let re = i * pi + q * pq;
+      let im = q * pi - i * pq;
+      self.prev = (i, q);
+
+      freqs.push(CENTRE_FREQ + im.atan2(re) * hz_per_radian);
+    }
+
+    freqs
+  }
+}
+
+
+pub fn demodulate(samples: &[i16], sample_rate: u32) -> Vec<f32> {
+  //"""Returns the instantaneous frequency of each sample, aligned with the
+  //audio"""
+  let mut demod = FmDemodulator::new(sample_rate);
+  let delay = demod.delay();
+
+  let mut freqs = demod.process(samples);
+  freqs.extend(demod.process(&vec![0; delay]));
+  freqs.split_off(delay)
+}
+
+
+pub fn mean_freq(track: &[f32]) -> f32 {
+  //"""Finds the average frequency over a section of the frequency track"""
+  if track.is_empty() {
+    return 0.0;
+  }
+  let sum: f64 = track.iter().map(|f| *f as f64).sum();
+  (sum / track.len() as f64) as f32
+}
+
+
+pub fn tone_snr(samples: &[i16], sample_rate: u32, freq: f32) -> f32 {
+  //"""Estimates the signal to noise ratio in dB of a steady tone, treating
+  //everything other than the tone as noise"""
+  if samples.is_empty() {
+    return 0.0;
+  }
+
+  // Goertzel filter for the power at the tone frequency
+  let coeff = 2.0 * (2.0 * PI * freq as f64 / sample_rate as f64).cos();
+  let (mut s1, mut s2) = (0.0, 0.0);
+  let mut total = 0.0;
+  for sample in samples {
+    let x = *sample as f64;
+    let s0 = x + coeff * s1 - s2;
+    s2 = s1;
+    s1 = s0;
+    total += x * x;
+  }
+
+  let n = samples.len() as f64;
+  let tone = 2.0 * (s1 * s1 + s2 * s2 - coeff * s1 * s2) / (n * n);
+  let total = total / n;
+  // Keep a clean synthetic tone from reporting an infinite ratio
+  let noise = (total - tone).max(tone * 1e-6);
+  if tone <= 0.0 {
+    return 0.0;
+  }
+  (10.0 * (tone / noise).log10()) as f32
+}
@@ -0,0 +1,293 @@
+use std::f64::consts::PI;
+use std::time::SystemTime;
+
+use crate::sstv::spec;
+use crate::sstv::img;
+use crate::sstv::color::{self, ColorRange};
+use crate::sstv::error::SSTVError;
+use crate::sstv::text::TextOverlay;
+use crate::sstv::fskid;
+
+
+type PixelVec = Vec<Vec<Vec<u8>>>;
+
+const SYNC_FREQ: f32 = 1200.0;
+const BLACK_FREQ: f32 = 1500.0;
+const LEADER_FREQ: f32 = 1900.0;
+const WHITE_FREQ: f32 = 2300.0;
+const VIS_ONE_FREQ: f32 = 1100.0;
+const VIS_ZERO_FREQ: f32 = 1300.0;
+
+const AMPLITUDE: f64 = 0.8 * i16::MAX as f64;
+
+
+pub fn calc_freq(lum: u8) -> f32 {
+  // Converts a 0-255 luminance byte into the SSTV pixel frequency range
+  BLACK_FREQ + (WHITE_FREQ - BLACK_FREQ) * lum as f32 / 255.0
+}
+
+
+pub(crate) struct Oscillator {
+  sample_rate: u32,
+  phase: f64,
+  time: f64,
+  pub samples: Vec<i16>,
+}
+
+impl Oscillator {
+  pub fn new(sample_rate: u32) -> Oscillator {
+    Oscillator { sample_rate, phase: 0.0, time: 0.0, samples: Vec::new() }
+  }
+
+  pub fn tone(&mut self, freq: f32, duration: f64) {
+    // Keep the running time in seconds so rounding to whole samples never
+    // accumulates into drift over the length of the image
+    self.time += duration;
+    let end = (self.time * self.sample_rate as f64).round() as usize;
+    let step = 2.0 * PI * freq as f64 / self.sample_rate as f64;
+
+    while self.samples.len() < end {
+      self.samples.push((self.phase.sin() * AMPLITUDE) as i16);
+      self.phase = (self.phase + step) % (2.0 * PI);
+    }
+  }
+
+  fn scan(&mut self, pixels: &[u8], duration: f32) {
+    let pixel_time = duration as f64 / pixels.len() as f64;
+    for lum in pixels {
+      self.tone(calc_freq(*lum), pixel_time);
+    }
+  }
+}
+
+
+pub struct SSTVEncoder {
+  mode: spec::Spec,
+  vis: usize,
+  sample_rate: u32,
+  image_data: PixelVec,
+  // Callsign sent as an FSK ID after the image
+  fsk_id: Option<String>,
+}
+
+
+// Create an SSTV encoder for turning an image into audio data
+impl SSTVEncoder {
+  pub fn new(image: &img::Image, mode: spec::Spec, sample_rate: u32) -> Result<Self, SSTVError> {
+    let vis = match spec::vis_code(&mode) {
+      Some(vis) => vis,
+      None => return Err(SSTVError::UnsupportedMode(mode.NAME)),
+    };
+    let image_data = SSTVEncoder::split_image(image, &mode)?;
+
+    Ok(SSTVEncoder {
+      mode,
+      vis,
+      sample_rate,
+      image_data,
+      fsk_id: None,
+    })
+  }
+
+
+  pub fn with_overlay(image: &img::Image, mode: spec::Spec, sample_rate: u32, overlay: &TextOverlay) -> Result<Self, SSTVError> {
+    //"""Like new, but first stamps the overlay on a copy of the image, with
+    //the mode and the current date and time filled in"""
+    let mut stamped = image.clone();
+    overlay.apply(&mut stamped, &mode, SystemTime::now());
+    SSTVEncoder::new(&stamped, mode, sample_rate)
+  }
+
+
+  pub fn set_fsk_id(&mut self, callsign: &str) -> Result<(), SSTVError> {
+    //"""Sends the callsign as an FSK ID after the image. Letters, digits
+    //and most punctuation up to 16 characters can be sent"""
+    fskid::encode_fsk_id(callsign, self.sample_rate)?;
+    self.fsk_id = Some(callsign.to_string());
+    Ok(())
+  }
+
+
+  pub fn encode(&self) -> Result<Vec<i16>, SSTVError> {
+    //"""Renders the calibration header, VIS, image and FSK ID as audio
+    //samples"""
+    let mut osc = Oscillator::new(self.sample_rate);
+
+    self.write_header(&mut osc);
+
+    if self.mode.HAS_START_SYNC {
+      osc.tone(SYNC_FREQ, self.mode.SYNC_PULSE as f64);
+    }
+
+    for line in 0..self.image_data.len() {
+      self.write_line(&mut osc, line);
+    }
+
+    if let Some(callsign) = &self.fsk_id {
+      fskid::write_fsk_id(&mut osc, callsign)?;
+    }
+
+    Ok(osc.samples)
+  }
+
+
+  fn write_header(&self, osc: &mut Oscillator) {
+    //"""Writes the calibration header followed by the VIS code"""
+    let bit_size = spec::VIS_BIT_SIZE as f64;
+
+    osc.tone(LEADER_FREQ, spec::BREAK_OFFSET as f64);
+    osc.tone(SYNC_FREQ, (spec::LEADER_OFFSET - spec::BREAK_OFFSET) as f64);
+    osc.tone(LEADER_FREQ, (spec::VIS_START_OFFSET - spec::LEADER_OFFSET) as f64);
+
+    // Start bit
+    osc.tone(SYNC_FREQ, bit_size);
+
+    if spec::vis_bit_count(self.vis) == 16 {
+      // MMSSTV's extended VIS is sent as two bytes LSB first, which
+      // already hold their parity bits
+      for bit_idx in 0..16 {
+        let bit = (self.vis >> bit_idx) & 1;
+        osc.tone(if bit == 1 { VIS_ONE_FREQ } else { VIS_ZERO_FREQ }, bit_size);
+      }
+    } else {
+      // 7 data bits LSB first, then an even parity bit
+      let mut parity = 0;
+      for bit_idx in 0..7 {
+        let bit = (self.vis >> bit_idx) & 1;
+        parity ^= bit;
+        osc.tone(if bit == 1 { VIS_ONE_FREQ } else { VIS_ZERO_FREQ }, bit_size);
+      }
+      osc.tone(if parity == 1 { VIS_ONE_FREQ } else { VIS_ZERO_FREQ }, bit_size);
+    }
+
+    // Stop bit
+    osc.tone(SYNC_FREQ, bit_size);
+  }
+
+
+  fn write_line(&self, osc: &mut Oscillator, line: usize) {
+    //"""Writes the sync pulses, porches and scans of a single line"""
+    let mode = &self.mode;
+
+    for chan in 0..mode.CHAN_COUNT {
+      let mut scan_time = mode.SCAN_TIME;
+      if mode.HAS_HALF_SCAN && chan > 0 {
+        // Robot mode has half-length second/third scans
+        scan_time = mode.HALF_SCAN_TIME;
+      }
+
+      if chan == mode.CHAN_SYNC {
+        osc.tone(SYNC_FREQ, mode.SYNC_PULSE as f64);
+        osc.tone(BLACK_FREQ, mode.SYNC_PORCH as f64);
+      } else if chan < mode.CHAN_SYNC {
+        // Scottie modes separate the scans sent before the sync pulse
+        osc.tone(BLACK_FREQ, mode.SEP_PULSE as f64);
+      }
+
+      osc.scan(&self.image_data[line][chan], scan_time);
+
+      if mode.HAS_HALF_SCAN {
+        if chan + 1 < mode.CHAN_COUNT {
+          // Robot separators tell the receiver which chroma scan follows
+          let odd_chroma = if mode.HAS_ALT_SCAN { line % 2 == 1 } else { chan > 0 };
+          let sep_freq = if odd_chroma { WHITE_FREQ } else { BLACK_FREQ };
+          osc.tone(sep_freq, mode.SEP_PULSE as f64);
+          osc.tone(LEADER_FREQ, mode.SEP_PORCH as f64);
+        }
+      } else if mode.CHAN_SYNC == 0 {
+        osc.tone(BLACK_FREQ, mode.SEP_PULSE as f64);
+      }
+    }
+  }
+
+
+  fn split_image(image: &img::Image, mode: &spec::Spec) -> Result<PixelVec, SSTVError> {
+    //"""Splits the image into the per-line channel scans of the mode. The
+    //image must already be the mode's size, see Image::fit_to_mode"""
+    let width = mode.LINE_WIDTH;
+    let channels = mode.CHAN_COUNT;
+    let height = mode.sync_lines();
+
+    if image.width() != width as u32 || image.height() != mode.LINE_COUNT as u32 {
+      return Err(SSTVError::ImageSize {
+        mode: mode.NAME.clone(),
+        expected: (width, mode.LINE_COUNT),
+        found: (image.width(), image.height()),
+      });
+    }
+
+    let ycbcr = |x: usize, y: usize| match image.get_pixel(x as u32, y as u32) {
+      Some(rgb) => color::rgb_to_ycbcr(rgb.r, rgb.g, rgb.b, ColorRange::Full),
+      None => color::rgb_to_ycbcr(0, 0, 0, ColorRange::Full),
+    };
+    let average = |a: u8, b: u8| ((a as u16 + b as u16) / 2) as u8;
+    let pixel = |x: usize, y: usize| {
+      let rgb = |y: usize| match image.get_pixel(x as u32, y as u32) {
+        Some(rgb) => (rgb.r, rgb.g, rgb.b),
+        None => (0, 0, 0),
+      };
+      if mode.HAS_DOUBLE_HEIGHT {
+        // Each line sent covers two rows of the image
+        let ((r0, g0, b0), (r1, g1, b1)) = (rgb(2 * y), rgb(2 * y + 1));
+        (average(r0, r1), average(g0, g1), average(b0, b1))
+      } else {
+        rgb(y)
+      }
+    };
+
+    let mut image_data: PixelVec = Vec::with_capacity(height);
+
+    for y in 0..height {
+      let mut line: Vec<Vec<u8>> = vec![Vec::with_capacity(width); channels];
+
+      for x in 0..width {
+        if mode.COLOR == spec::ColFmt::GBR {
+          // Martin and Scottie modes
+          let (r, g, b) = pixel(x, y);
+          line[0].push(g);
+          line[1].push(b);
+          line[2].push(r);
+        } else if mode.COLOR == spec::ColFmt::RGB {
+          // Wraase SC2 modes
+          let (r, g, b) = pixel(x, y);
+          line[0].push(r);
+          line[1].push(g);
+          line[2].push(b);
+        } else if mode.COLOR == spec::ColFmt::YUV {
+          if mode.HAS_DUAL_LINE {
+            // PD modes send both lines of a pair with their averaged chroma
+            let (lum0, cb0, cr0) = ycbcr(x, 2 * y);
+            let (lum1, cb1, cr1) = ycbcr(x, 2 * y + 1);
+            line[0].push(lum0);
+            line[1].push(average(cr0, cr1));
+            line[2].push(average(cb0, cb1));
+            line[3].push(lum1);
+          } else if mode.HAS_ALT_SCAN {
+            // R36 sends Cr on even lines and Cb on odd lines, each shared
+            // by a pair of lines
+            let pair = y - y % 2;
+            let (lum, _, _) = ycbcr(x, y);
+            let (_, cb0, cr0) = ycbcr(x, pair);
+            let (_, cb1, cr1) = ycbcr(x, pair + 1);
+            line[0].push(lum);
+            line[1].push(if y % 2 == 0 { average(cr0, cr1) } else { average(cb0, cb1) });
+          } else {
+            // R72
+            let (lum, cb, cr) = ycbcr(x, y);
+            line[0].push(lum);
+            line[1].push(cr);
+            line[2].push(cb);
+          }
+        } else {
+          // Robot black and white modes
+          let (lum, _, _) = ycbcr(x, y);
+          line[0].push(lum);
+        }
+      }
+
+      image_data.push(line);
+    }
+
+    Ok(image_data)
+  }
+}
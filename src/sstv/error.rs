@@ -0,0 +1,53 @@
+use std::fmt;
+use std::io;
+
+
+#[derive(Debug)]
+pub enum SSTVError {
+  HeaderNotFound,
+  SyncNotFound,
+  VisParity,
+  UnsupportedVis(usize),
+  UnsupportedMode(String),
+  InvalidCallsign(String),
+  ImageSize { mode: String, expected: (usize, usize), found: (u32, u32) },
+  TruncatedVis,
+  TruncatedAudio { line: usize },
+  Io(io::Error),
+  AudioFormat(String),
+}
+
+impl fmt::Display for SSTVError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SSTVError::HeaderNotFound => write!(f, "Couldn't find SSTV header in the given audio file"),
+      SSTVError::SyncNotFound => write!(f, "Couldn't find a sync pulse in the given audio"),
+      SSTVError::VisParity => write!(f, "Error decoding VIS header (invalid parity bit)"),
+      SSTVError::UnsupportedVis(vis) => write!(f, "SSTV mode is unsupported (VIS: {})", vis),
+      SSTVError::UnsupportedMode(name) => write!(f, "SSTV mode {} is unsupported", name),
+      SSTVError::InvalidCallsign(call) => write!(f, "Callsign {:?} can't be sent as an FSK ID", call),
+      SSTVError::ImageSize { mode, expected, found } => {
+        write!(f, "{} sends {}x{} images, not {}x{}", mode, expected.0, expected.1, found.0, found.1)
+      },
+      SSTVError::TruncatedVis => write!(f, "Reached end of audio within the VIS code"),
+      SSTVError::TruncatedAudio { line } => write!(f, "Reached end of audio at line {}", line),
+      SSTVError::Io(err) => write!(f, "I/O error: {}", err),
+      SSTVError::AudioFormat(msg) => write!(f, "Couldn't read audio: {}", msg),
+    }
+  }
+}
+
+impl std::error::Error for SSTVError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      SSTVError::Io(err) => Some(err),
+      _ => None,
+    }
+  }
+}
+
+impl From<io::Error> for SSTVError {
+  fn from(err: io::Error) -> Self {
+    SSTVError::Io(err)
+  }
+}
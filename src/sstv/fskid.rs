@@ -0,0 +1,197 @@
+// FSK callsign ID sent after the image by MMSSTV, QSSTV and others. Each
+// character is sent as six bits, least significant first
+
+use crate::sstv::demod::mean_freq;
+use crate::sstv::encode::Oscillator;
+use crate::sstv::error::SSTVError;
+
+
+const BIT_TIME: f32 = 0.022;
+const ONE_FREQ: f32 = 1900.0;
+const ZERO_FREQ: f32 = 2100.0;
+// Sent before the callsign to mark the start of the ID
+const PREAMBLE: [u8; 2] = [0x20, 0x2A];
+// Sent after the callsign, followed by the XOR of its characters
+const END_CODE: u8 = 0x01;
+pub const MAX_ID_LEN: usize = 16;
+
+// Audio after the image searched for the start of an ID, in seconds
+const SEARCH_TIME: f32 = 1.0;
+// Furthest a bit's frequency can be from the nearer tone, in Hz
+const BIT_TOLERANCE: f32 = 100.0;
+
+
+fn to_code(c: char) -> Option<u8> {
+  // Characters are sent less 0x20, so only space to underscore fit in six
+  // bits, and ! would read as the end of the ID
+  let c = c.to_ascii_uppercase();
+  match c {
+    ' ' | '"'..='_' => Some(c as u8 - 0x20),
+    _ => None,
+  }
+}
+
+
+fn id_codes(callsign: &str) -> Result<Vec<u8>, SSTVError> {
+  //"""Six-bit codes sent for a callsign, from the preamble to the
+  //checksum"""
+  let invalid = || SSTVError::InvalidCallsign(callsign.to_string());
+  if callsign.is_empty() || callsign.chars().count() > MAX_ID_LEN {
+    return Err(invalid());
+  }
+
+  let mut codes = PREAMBLE.to_vec();
+  let mut checksum = 0;
+  for c in callsign.chars() {
+    let code = to_code(c).ok_or_else(invalid)?;
+    checksum ^= code;
+    codes.push(code);
+  }
+  codes.push(END_CODE);
+  codes.push(checksum);
+  Ok(codes)
+}
+
+
+pub fn max_id_time() -> f32 {
+  // Audio after an image that may hold its ID, in seconds
+  SEARCH_TIME + (MAX_ID_LEN + PREAMBLE.len() + 2) as f32 * 6.0 * BIT_TIME
+}
+
+
+fn write_codes(osc: &mut Oscillator, codes: &[u8]) {
+  for code in codes {
+    for bit in 0..6 {
+      let freq = if code >> bit & 1 == 1 { ONE_FREQ } else { ZERO_FREQ };
+      osc.tone(freq, BIT_TIME as f64);
+    }
+  }
+}
+
+
+pub(crate) fn write_fsk_id(osc: &mut Oscillator, callsign: &str) -> Result<(), SSTVError> {
+  write_codes(osc, &id_codes(callsign)?);
+  Ok(())
+}
+
+
+pub fn encode_fsk_id(callsign: &str, sample_rate: u32) -> Result<Vec<i16>, SSTVError> {
+  //"""Renders the FSK ID of a callsign as audio samples, to append to a
+  //transmission"""
+  let mut osc = Oscillator::new(sample_rate);
+  write_fsk_id(&mut osc, callsign)?;
+  Ok(osc.samples)
+}
+
+
+struct BitReader<'a> {
+  track: &'a [f32],
+  bit_samples: f32,
+  freq_offset: f32,
+  // Sample where the next bit starts
+  pos: f32,
+}
+
+impl BitReader<'_> {
+  fn bit(&mut self) -> Option<u8> {
+    // Only the middle half of each bit is used, to allow for timing error
+    let start = (self.pos + self.bit_samples / 4.0) as usize;
+    let end = (self.pos + 3.0 * self.bit_samples / 4.0) as usize;
+    self.pos += self.bit_samples;
+    let freq = mean_freq(self.track.get(start..end)?) - self.freq_offset;
+
+    if (freq - ONE_FREQ).abs() <= BIT_TOLERANCE {
+      Some(1)
+    } else if (freq - ZERO_FREQ).abs() <= BIT_TOLERANCE {
+      Some(0)
+    } else {
+      None
+    }
+  }
+
+  fn code(&mut self) -> Option<u8> {
+    let mut code = 0;
+    for bit in 0..6 {
+      code |= self.bit()? << bit;
+    }
+    Some(code)
+  }
+
+  fn id(&mut self) -> Option<(String, usize)> {
+    // Callsign and the sample after the checksum, if an ID starts here
+    if PREAMBLE.iter().any(|code| self.code() != Some(*code)) {
+      return None;
+    }
+
+    let mut callsign = String::new();
+    let mut checksum = 0;
+    let mut code = self.code()?;
+    while code != END_CODE {
+      // Anything longer isn't an ID
+      if callsign.len() == MAX_ID_LEN {
+        return None;
+      }
+      checksum ^= code;
+      callsign.push((code + 0x20) as char);
+      code = self.code()?;
+    }
+
+    if callsign.is_empty() || self.code()? != checksum {
+      return None;
+    }
+    Some((callsign, self.pos as usize))
+  }
+}
+
+
+pub(crate) fn find_fsk_id(track: &[f32], sample_rate: u32, freq_offset: f32) -> Option<(String, usize)> {
+  //"""Looks for an FSK ID starting in the first second of the track,
+  //returning the callsign and the sample after its last bit"""
+  let bit_samples = BIT_TIME * sample_rate as f32;
+  let search_end = ((SEARCH_TIME * sample_rate as f32) as usize).min(track.len());
+  let step = ((bit_samples / 8.0) as usize).max(1);
+
+  (0..search_end).step_by(step).find_map(|start| {
+    let mut reader = BitReader { track, bit_samples, freq_offset, pos: start as f32 };
+    reader.id()
+  })
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::sstv::demod::demodulate;
+
+  const SAMPLE_RATE: u32 = 11025;
+
+  fn find(samples: &[i16]) -> Option<String> {
+    find_fsk_id(&demodulate(samples, SAMPLE_RATE), SAMPLE_RATE, 0.0).map(|(callsign, _)| callsign)
+  }
+
+  #[test]
+  fn callsign_round_trip() {
+    let samples = encode_fsk_id("n0call", SAMPLE_RATE).unwrap();
+    assert_eq!(find(&samples).as_deref(), Some("N0CALL"));
+  }
+
+  #[test]
+  fn longest_callsign_is_read() {
+    let callsign = "A".repeat(MAX_ID_LEN);
+    let samples = encode_fsk_id(&callsign, SAMPLE_RATE).unwrap();
+    assert_eq!(find(&samples), Some(callsign));
+  }
+
+  #[test]
+  fn overlong_callsign_is_rejected() {
+    assert!(encode_fsk_id(&"A".repeat(MAX_ID_LEN + 1), SAMPLE_RATE).is_err());
+
+    // Sent anyway by another program, with an otherwise valid checksum
+    let chars = vec![to_code('A').unwrap(); MAX_ID_LEN + 1];
+    let checksum = chars.iter().fold(0, |sum, code| sum ^ code);
+    let codes = [&PREAMBLE[..], &chars, &[END_CODE, checksum]].concat();
+    let mut osc = Oscillator::new(SAMPLE_RATE);
+    write_codes(&mut osc, &codes);
+    assert_eq!(find(&osc.samples), None);
+  }
+}
@@ -1,29 +1,144 @@
 #![allow(unused)]
 
 use std::path::Path;
-use std::io::Write;
+use std::io::{self, Read, Write};
 use std::fs::File;
 
 use crate::sstv::crypt;
+use crate::sstv::meta::PngMetadata;
 
 
+const PNG_SIGNATURE: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+// Starting column and row, and spacing, of the pixels in each Adam7 pass
+const ADAM7_PASSES: [(u32, u32, u32, u32); 7] = [
+  (0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2),
+];
+// Largest image read, in pixels, so a corrupt header can't exhaust memory
+const MAX_PIXELS: u64 = 1 << 28;
+
+
+fn invalid(msg: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+
+fn read_chunks(bytes: &[u8]) -> io::Result<Vec<(&[u8], &[u8])>> {
+  //"""Splits a PNG file into the (tag, data) of its chunks up to IEND,
+  //checking the CRC of each"""
+  if !bytes.starts_with(&PNG_SIGNATURE) {
+    return Err(invalid("not a PNG file"));
+  }
+  let be_u32 = |pos: usize| u32::from_be_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]]);
+
+  // Each chunk is its length, tag, data and CRC of the tag and data
+  let mut chunks: Vec<(&[u8], &[u8])> = Vec::new();
+  let mut pos = PNG_SIGNATURE.len();
+  loop {
+    if pos + 8 > bytes.len() {
+      return Err(invalid("truncated PNG file"));
+    }
+    let length = be_u32(pos) as usize;
+    let tag = &bytes[pos + 4..pos + 8];
+    let end = pos + 8 + length;
+    if end + 4 > bytes.len() {
+      return Err(invalid("truncated PNG file"));
+    }
+    if crypt::crc(&bytes[pos + 4..end]) != be_u32(end) {
+      return Err(invalid("PNG chunk checksum mismatch"));
+    }
+
+    chunks.push((tag, &bytes[pos + 8..end]));
+    if tag == b"IEND" {
+      return Ok(chunks);
+    }
+    pos = end + 4;
+  }
+}
+
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+  // Whichever neighbour is closest to a + b - c
+  let p = a as i16 + b as i16 - c as i16;
+  let pa = (p - a as i16).abs();
+  let pb = (p - b as i16).abs();
+  let pc = (p - c as i16).abs();
+  if pa <= pb && pa <= pc {
+    a
+  } else if pb <= pc {
+    b
+  } else {
+    c
+  }
+}
+
+
+fn unfilter(filter: u8, line: &mut [u8], prev: &[u8], bpp: usize) -> io::Result<()> {
+  // Undoes a PNG scanline filter in place, given the unfiltered line above
+  for idx in 0..line.len() {
+    let a = if idx >= bpp { line[idx - bpp] } else { 0 };
+    let b = prev[idx];
+    let c = if idx >= bpp { prev[idx - bpp] } else { 0 };
+    let predicted = match filter {
+      0 => 0,
+      1 => a,
+      2 => b,
+      3 => ((a as u16 + b as u16) / 2) as u8,
+      4 => paeth(a, b, c),
+      _ => return Err(invalid("unknown PNG filter type")),
+    };
+    line[idx] = line[idx].wrapping_add(predicted);
+  }
+  Ok(())
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct RGB {
-  r: u8,
-  g: u8,
-  b: u8,
+  pub r: u8,
+  pub g: u8,
+  pub b: u8,
 }
 
+#[derive(Clone)]
 pub struct Image {
   height: u32,
   width: u32,
   data: Vec<u8>,
+  // Written as a one channel PNG, for black and white modes
+  greyscale: bool,
 }
 
 impl Image {
     pub fn new(height: u32, width: u32) -> Image {
       let size = 3 * height * width;
       let data = vec![0; size as usize];
-      Image { height, width, data }
+      Image { height, width, data, greyscale: false }
+    }
+
+    pub fn width(&self) -> u32 {
+      self.width
+    }
+
+    pub fn height(&self) -> u32 {
+      self.height
+    }
+
+    pub fn data(&self) -> &[u8] {
+      // Packed 8-bit RGB, row by row
+      &self.data
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+      self.data
+    }
+
+    pub fn is_greyscale(&self) -> bool {
+      self.greyscale
+    }
+
+    pub fn set_greyscale(&mut self, greyscale: bool) {
+      // Only the red channel of a greyscale image is written to a PNG
+      self.greyscale = greyscale;
     }
 
     fn buffer_size(&self) -> u32 {
@@ -51,6 +166,29 @@ impl Image {
       }
     }
 
+    pub fn set_pixel(&mut self, x: u32, y: u32, color: (u8, u8, u8)) -> bool {
+      match self.get_offset(x, y) {
+        Some(offset) => {
+          self.data[offset] = color.0;
+          self.data[offset + 1] = color.1;
+          self.data[offset + 2] = color.2;
+          true
+        },
+        None => false
+      }
+    }
+
+    pub fn set_row(&mut self, y: u32, row: &[u8]) -> bool {
+      // Copy a packed RGB row into the image
+      match self.get_offset(0, y) {
+        Some(offset) if row.len() == 3 * self.width as usize => {
+          self.data[offset..offset + row.len()].copy_from_slice(row);
+          true
+        },
+        _ => false
+      }
+    }
+
     pub fn set_pixel_usize(&mut self, x: u32, y: u32, color: (usize, usize, usize)) -> bool {
       match self.get_offset(x, y) {
         Some(offset) => {
@@ -66,47 +204,228 @@ impl Image {
     pub fn write_file(&self, filename: &str) -> std::io::Result<()> {
 
       let path = Path::new(filename);
-      let mut file = File::create(&path)?;
+      let mut file = File::create(path)?;
       let header = format!("P6 {} {} 255\n", self.width, self.height);
-      file.write(header.as_bytes())?;
-      file.write(&self.data)?;
+      file.write_all(header.as_bytes())?;
+      file.write_all(&self.data)?;
 
       Ok(())
     }
 
 
 
+    pub fn open(filename: &str) -> std::io::Result<Image> {
+      // Read a PNG or binary PPM file, told apart by their signatures
+      let bytes = std::fs::read(Path::new(filename))?;
+      if bytes.starts_with(&PNG_SIGNATURE) {
+        Image::decode_png(&bytes)
+      } else {
+        Image::decode_ppm(&bytes)
+      }
+    }
+
+
+    pub fn read_file(filename: &str) -> std::io::Result<Image> {
+      // Read an 8-bit binary PPM (P6) file
+      Image::decode_ppm(&std::fs::read(Path::new(filename))?)
+    }
+
+
+    pub fn read_file_png(filename: &str) -> std::io::Result<Image> {
+      // Read an 8-bit greyscale, RGB or palette PNG file, with or without
+      // alpha and Adam7 interlacing. Alpha is dropped
+      Image::decode_png(&std::fs::read(Path::new(filename))?)
+    }
+
+
+    fn decode_ppm(bytes: &[u8]) -> std::io::Result<Image> {
+      // The header is four whitespace separated fields, which may have
+      // comments between them
+      let mut fields: Vec<String> = Vec::new();
+      let mut pos = 0;
+      while fields.len() < 4 {
+        match bytes.get(pos) {
+          None => return Err(invalid("truncated PPM header")),
+          Some(b'#') => {
+            while pos < bytes.len() && bytes[pos] != b'\n' {
+              pos += 1;
+            }
+          },
+          Some(c) if c.is_ascii_whitespace() => pos += 1,
+          Some(_) => {
+            let start = pos;
+            while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+              pos += 1;
+            }
+            fields.push(String::from_utf8_lossy(&bytes[start..pos]).to_string());
+          },
+        }
+      }
+      // A single whitespace byte separates the header from the pixels
+      pos += 1;
+
+      if fields[0] != "P6" {
+        return Err(invalid("only binary PPM (P6) files are supported"));
+      }
+      let number = |field: &str| field.parse::<u32>().map_err(|_| invalid("bad number in PPM header"));
+      let width = number(&fields[1])?;
+      let height = number(&fields[2])?;
+      if number(&fields[3])? != 255 {
+        return Err(invalid("only 8-bit PPM files are supported"));
+      }
+
+      let size = 3 * width as usize * height as usize;
+      if bytes.len() < pos + size {
+        return Err(invalid("truncated PPM pixel data"));
+      }
+      Ok(Image { height, width, data: bytes[pos..pos + size].to_vec(), greyscale: false })
+    }
+
+
+    fn decode_png(bytes: &[u8]) -> std::io::Result<Image> {
+      let be_u32 = |data: &[u8]| u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+
+      // (width, height, colour type, interlace method)
+      let mut header: Option<(u32, u32, u8, u8)> = None;
+      let mut palette: Vec<u8> = Vec::new();
+      let mut compressed: Vec<u8> = Vec::new();
+
+      for (tag, data) in read_chunks(bytes)? {
+        match tag {
+          b"IHDR" => {
+            if data.len() != 13 {
+              return Err(invalid("bad PNG header"));
+            }
+            if data[8] != 8 {
+              return Err(invalid("only 8-bit PNG files are supported"));
+            }
+            if data[10] != 0 || data[11] != 0 || data[12] > 1 {
+              return Err(invalid("unknown PNG compression, filter or interlace method"));
+            }
+            header = Some((be_u32(&data[0..4]), be_u32(&data[4..8]), data[9], data[12]));
+          },
+          b"PLTE" => palette = data.to_vec(),
+          b"IDAT" => compressed.extend(data),
+          b"IEND" => (),
+          // Ancillary chunks have a lower case first letter and can be skipped
+          _ if tag[0].is_ascii_lowercase() => (),
+          _ => return Err(invalid("unsupported critical PNG chunk")),
+        }
+      }
+
+      let (width, height, color_type, interlace) = header.ok_or_else(|| invalid("missing PNG header"))?;
+      let channels = match color_type {
+        0 | 3 => 1,
+        4 => 2,
+        2 => 3,
+        6 => 4,
+        _ => return Err(invalid("unknown PNG colour type")),
+      };
+      if color_type == 3 && palette.is_empty() {
+        return Err(invalid("palette PNG without a palette"));
+      }
+      if width == 0 || height == 0 || width as u64 * height as u64 > MAX_PIXELS {
+        return Err(invalid("bad PNG image size"));
+      }
+
+      let raw = crypt::decode_data_zlib(&compressed)?;
+      let passes: &[(u32, u32, u32, u32)] = if interlace == 1 { &ADAM7_PASSES } else { &[(0, 0, 1, 1)] };
+      let mut image = Image::new(height, width);
+      image.set_greyscale(color_type == 0 || color_type == 4);
+      let mut pos = 0;
+
+      for &(x0, y0, dx, dy) in passes {
+        // Passes covering no pixels are left out of the data entirely
+        let pass_width = (width + dx - 1 - x0.min(width)) / dx;
+        let pass_height = (height + dy - 1 - y0.min(height)) / dy;
+        if pass_width == 0 || pass_height == 0 {
+          continue;
+        }
+
+        let stride = pass_width as usize * channels;
+        let mut prev = vec![0u8; stride];
+        for row in 0..pass_height {
+          if pos + 1 + stride > raw.len() {
+            return Err(invalid("truncated PNG image data"));
+          }
+          let mut line = raw[pos + 1..pos + 1 + stride].to_vec();
+          unfilter(raw[pos], &mut line, &prev, channels)?;
+          pos += 1 + stride;
+
+          for (col, px) in line.chunks(channels).enumerate() {
+            let color = match color_type {
+              0 | 4 => (px[0], px[0], px[0]),
+              3 => {
+                let idx = 3 * px[0] as usize;
+                match palette.get(idx..idx + 3) {
+                  Some(entry) => (entry[0], entry[1], entry[2]),
+                  None => return Err(invalid("PNG palette index out of range")),
+                }
+              },
+              _ => (px[0], px[1], px[2]),
+            };
+            image.set_pixel(x0 + col as u32 * dx, y0 + row * dy, color);
+          }
+          prev = line;
+        }
+      }
+      Ok(image)
+    }
+
+
+
     fn write_chunk(outfile: &mut File, tag: &[u8], data: &Vec<u8>) -> std::io::Result<()> {
       // Write a PNG chunk to the output file, including length and
       // checksum.
-      outfile.write(&(data.len() as u32).to_be_bytes())?;
-      outfile.write(tag)?;
-      outfile.write(&data)?;
+      outfile.write_all(&(data.len() as u32).to_be_bytes())?;
+      outfile.write_all(tag)?;
+      outfile.write_all(data)?;
       let mut all_data = tag.to_vec();
       all_data.extend(data);
       let checksum = crypt::crc(&all_data);
-      outfile.write(&checksum.to_be_bytes())?;
+      outfile.write_all(&checksum.to_be_bytes())?;
       Ok(())
     }
 
 
+    pub fn read_png_metadata(filename: &str) -> std::io::Result<PngMetadata> {
+      // Read the text and time chunks of a PNG file without decoding it
+      let bytes = std::fs::read(Path::new(filename))?;
+      let mut metadata = PngMetadata::new();
+      for (tag, data) in read_chunks(&bytes)? {
+        metadata.read_chunk(tag, data)?;
+      }
+      Ok(metadata)
+    }
+
+
     pub fn write_file_png(&self, filename: &str) -> std::io::Result<()> {
+      self.write_file_png_with_metadata(filename, &PngMetadata::new())
+    }
+
+
+    pub fn write_file_png_with_metadata(&self, filename: &str, metadata: &PngMetadata) -> std::io::Result<()> {
+      // Encode the metadata first so a bad keyword doesn't leave a
+      // half-written file
+      let meta_chunks = metadata.to_chunks()?;
 
       let path: &Path = Path::new(filename);
-      let mut file: File = File::create(&path)?;
+      let mut file: File = File::create(path)?;
 
-      // File metadata
+      // File metadata. Images from black and white modes are written with
+      // one channel
+      let greyscale = self.is_greyscale();
       let bit_depth: u8 = 8;
-      let color_type: u8 = 2;
+      let color_type: u8 = if greyscale { 0 } else { 2 };
       let compression_method: u8 = 0;
       let filter_method: u8 = 0;
       let interlace_method: u8 = 0;
 
       let png_sig: Vec<u8> = vec![0x89,0x50,0x4E,0x47,0x0D,0x0A,0x1A,0x0A]; // PNG SIGNATURE
-      file.write(&png_sig)?;
+      file.write_all(&png_sig)?;
 
       // png IHDR
-      file.write(&[0x00, 0x00, 0x00, 0x0D])?; // length
+      file.write_all(&[0x00, 0x00, 0x00, 0x0D])?; // length
 
       let mut header: Vec<u8> = vec![0x49, 0x48, 0x44, 0x52]; // IHDR bytes
       header.extend(self.width.to_be_bytes());
@@ -114,15 +433,23 @@ impl Image {
       header.extend(&[bit_depth, color_type, 0x00, 0x00, interlace_method]);
       let header_crc = crypt::crc(&header);
 
-      file.write(&header)?;
-      file.write(&header_crc.to_be_bytes())?;
+      file.write_all(&header)?;
+      file.write_all(&header_crc.to_be_bytes())?;
+
+      for (tag, data) in &meta_chunks {
+        Image::write_chunk(&mut file, tag, data)?;
+      }
 
       let mut new_slice: Vec<u8> =  vec![];
 
       let header = [0x49, 0x44, 0x41, 0x54]; // IDAT
       self.data.chunks(3 * self.width as usize).for_each(|slice| {
         new_slice.extend(&[0]);
-        new_slice.extend(slice);
+        if greyscale {
+          new_slice.extend(slice.iter().step_by(3));
+        } else {
+          new_slice.extend(slice);
+        }
       });
 
       let deflate_data = crypt::encode_data_zlib(&new_slice);
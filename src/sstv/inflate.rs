@@ -0,0 +1,337 @@
+// Decompresses zlib streams (RFC 1950/1951), for reading PNG image data
+
+use std::io;
+
+
+// Base lengths and extra bits of length codes 257-285
+const LENGTH_BASE: [u16; 29] = [
+  3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+  35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+  0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+  3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+// Base distances and extra bits of distance codes 0-29
+const DIST_BASE: [u16; 30] = [
+  1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+  257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+  0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+  7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+// Order the code length code lengths are sent in by dynamic blocks
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+const MAX_BITS: usize = 15;
+
+
+fn invalid(msg: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+
+// Reads the compressed stream least significant bit first
+struct BitReader<'a> {
+  data: &'a [u8],
+  pos: usize,
+  bit: u32,
+}
+
+impl<'a> BitReader<'a> {
+  fn new(data: &'a [u8]) -> Self {
+    BitReader { data, pos: 0, bit: 0 }
+  }
+
+  fn bits(&mut self, count: u32) -> io::Result<u32> {
+    let mut value = 0;
+    for idx in 0..count {
+      let byte = *self.data.get(self.pos).ok_or_else(|| invalid("truncated deflate data"))?;
+      value |= (((byte >> self.bit) & 1) as u32) << idx;
+      self.bit += 1;
+      if self.bit == 8 {
+        self.bit = 0;
+        self.pos += 1;
+      }
+    }
+    Ok(value)
+  }
+
+  fn align_byte(&mut self) {
+    if self.bit > 0 {
+      self.bit = 0;
+      self.pos += 1;
+    }
+  }
+}
+
+
+// Canonical Huffman code, stored as the number of codes of each length
+// and the symbols in code order
+struct Huffman {
+  counts: [u16; MAX_BITS + 1],
+  symbols: Vec<u16>,
+}
+
+impl Huffman {
+  fn new(lengths: &[u8]) -> io::Result<Self> {
+    let mut counts = [0u16; MAX_BITS + 1];
+    for len in lengths {
+      counts[*len as usize] += 1;
+    }
+    counts[0] = 0;
+
+    // Reject codes with more symbols than fit in their lengths
+    let mut left: i32 = 1;
+    for count in &counts[1..] {
+      left = 2 * left - *count as i32;
+      if left < 0 {
+        return Err(invalid("over-subscribed Huffman code"));
+      }
+    }
+
+    let mut offsets = [0u16; MAX_BITS + 2];
+    for len in 1..=MAX_BITS {
+      offsets[len + 1] = offsets[len] + counts[len];
+    }
+    let mut symbols = vec![0; offsets[MAX_BITS + 1] as usize];
+    for (symbol, len) in lengths.iter().enumerate() {
+      if *len > 0 {
+        symbols[offsets[*len as usize] as usize] = symbol as u16;
+        offsets[*len as usize] += 1;
+      }
+    }
+    Ok(Huffman { counts, symbols })
+  }
+
+  fn decode(&self, reader: &mut BitReader) -> io::Result<u16> {
+    // Codes are packed most significant bit first, one bit at a time
+    let mut code: i32 = 0;
+    let mut first: i32 = 0;
+    let mut index: i32 = 0;
+    for len in 1..=MAX_BITS {
+      code |= reader.bits(1)? as i32;
+      let count = self.counts[len] as i32;
+      if code - first < count {
+        return Ok(self.symbols[(index + code - first) as usize]);
+      }
+      index += count;
+      first = (first + count) << 1;
+      code <<= 1;
+    }
+    Err(invalid("bad Huffman code"))
+  }
+}
+
+
+fn fixed_codes() -> io::Result<(Huffman, Huffman)> {
+  let mut lengths = [0u8; 288];
+  lengths[..144].fill(8);
+  lengths[144..256].fill(9);
+  lengths[256..280].fill(7);
+  lengths[280..].fill(8);
+  Ok((Huffman::new(&lengths)?, Huffman::new(&[5; 30])?))
+}
+
+
+fn dynamic_codes(reader: &mut BitReader) -> io::Result<(Huffman, Huffman)> {
+  let lit_count = reader.bits(5)? as usize + 257;
+  let dist_count = reader.bits(5)? as usize + 1;
+  let code_count = reader.bits(4)? as usize + 4;
+  if lit_count > 286 || dist_count > 30 {
+    return Err(invalid("bad dynamic block header"));
+  }
+
+  let mut code_lengths = [0u8; 19];
+  for idx in CODE_LENGTH_ORDER.iter().take(code_count) {
+    code_lengths[*idx] = reader.bits(3)? as u8;
+  }
+  let code_huffman = Huffman::new(&code_lengths)?;
+
+  // Literal/length and distance code lengths are sent as one run-length
+  // coded sequence
+  let mut lengths: Vec<u8> = Vec::with_capacity(lit_count + dist_count);
+  while lengths.len() < lit_count + dist_count {
+    let symbol = code_huffman.decode(reader)?;
+    let (value, repeat) = match symbol {
+      0..=15 => (symbol as u8, 1),
+      16 => {
+        let last = *lengths.last().ok_or_else(|| invalid("repeat with no previous length"))?;
+        (last, 3 + reader.bits(2)?)
+      },
+      17 => (0, 3 + reader.bits(3)?),
+      _ => (0, 11 + reader.bits(7)?),
+    };
+    if lengths.len() + repeat as usize > lit_count + dist_count {
+      return Err(invalid("too many code lengths"));
+    }
+    lengths.resize(lengths.len() + repeat as usize, value);
+  }
+
+  if lengths[256] == 0 {
+    return Err(invalid("no end of block code"));
+  }
+  Ok((Huffman::new(&lengths[..lit_count])?, Huffman::new(&lengths[lit_count..])?))
+}
+
+
+fn inflate_block(reader: &mut BitReader, lit: &Huffman, dist: &Huffman, output: &mut Vec<u8>) -> io::Result<()> {
+  loop {
+    let symbol = lit.decode(reader)? as usize;
+    if symbol < 256 {
+      output.push(symbol as u8);
+    } else if symbol == 256 {
+      return Ok(());
+    } else {
+      let idx = symbol - 257;
+      if idx >= LENGTH_BASE.len() {
+        return Err(invalid("bad length code"));
+      }
+      let length = LENGTH_BASE[idx] as usize + reader.bits(LENGTH_EXTRA[idx] as u32)? as usize;
+
+      let idx = dist.decode(reader)? as usize;
+      if idx >= DIST_BASE.len() {
+        return Err(invalid("bad distance code"));
+      }
+      let distance = DIST_BASE[idx] as usize + reader.bits(DIST_EXTRA[idx] as u32)? as usize;
+      if distance > output.len() {
+        return Err(invalid("distance before start of data"));
+      }
+
+      // The copy may overlap the bytes it produces
+      let start = output.len() - distance;
+      for offset in 0..length {
+        output.push(output[start + offset]);
+      }
+    }
+  }
+}
+
+
+fn inflate(data: &[u8]) -> io::Result<(Vec<u8>, usize)> {
+  //"""Decompresses raw deflate data, returning it with the number of bytes
+  //of input used"""
+  let mut reader = BitReader::new(data);
+  let mut output: Vec<u8> = Vec::new();
+
+  loop {
+    let last = reader.bits(1)? == 1;
+    match reader.bits(2)? {
+      0 => {
+        // Stored block, byte aligned with its length and one's complement
+        reader.align_byte();
+        let pos = reader.pos;
+        if pos + 4 > data.len() {
+          return Err(invalid("truncated stored block"));
+        }
+        let len = u16::from_le_bytes([data[pos], data[pos + 1]]) as usize;
+        let nlen = u16::from_le_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        if len != !nlen & 0xFFFF {
+          return Err(invalid("bad stored block length"));
+        }
+        if pos + 4 + len > data.len() {
+          return Err(invalid("truncated stored block"));
+        }
+        output.extend(&data[pos + 4..pos + 4 + len]);
+        reader.pos = pos + 4 + len;
+      },
+      1 => {
+        let (lit, dist) = fixed_codes()?;
+        inflate_block(&mut reader, &lit, &dist, &mut output)?;
+      },
+      2 => {
+        let (lit, dist) = dynamic_codes(&mut reader)?;
+        inflate_block(&mut reader, &lit, &dist, &mut output)?;
+      },
+      _ => return Err(invalid("bad deflate block type")),
+    }
+
+    if last {
+      reader.align_byte();
+      return Ok((output, reader.pos));
+    }
+  }
+}
+
+
+fn adler32(data: &[u8]) -> u32 {
+  let mut a: u32 = 1;
+  let mut b: u32 = 0;
+  for chunk in data.chunks(5552) {
+    for byte in chunk {
+      a += *byte as u32;
+      b += a;
+    }
+    a %= 65521;
+    b %= 65521;
+  }
+  (b << 16) | a
+}
+
+
+pub fn decode_zlib(data: &[u8]) -> io::Result<Vec<u8>> {
+  //"""Decompresses a zlib stream, checking its header and checksum"""
+  if data.len() < 6 {
+    return Err(invalid("truncated zlib stream"));
+  }
+  let (cmf, flg) = (data[0], data[1]);
+  if cmf & 0x0F != 8 || cmf >> 4 > 7 || u16::from_be_bytes([cmf, flg]) % 31 > 0 {
+    return Err(invalid("bad zlib header"));
+  }
+  if flg & 0x20 != 0 {
+    return Err(invalid("zlib preset dictionaries aren't supported"));
+  }
+
+  let (output, used) = inflate(&data[2..])?;
+  let checksum = data.get(2 + used..6 + used).ok_or_else(|| invalid("missing zlib checksum"))?;
+  if u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]) != adler32(&output) {
+    return Err(invalid("zlib checksum mismatch"));
+  }
+  Ok(output)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn stored(data: &[u8]) -> Vec<u8> {
+    // zlib stream holding the data in a single uncompressed block
+    let mut stream = vec![0x78, 0x01, 0x01];
+    stream.extend((data.len() as u16).to_le_bytes());
+    stream.extend((!(data.len() as u16)).to_le_bytes());
+    stream.extend(data);
+    stream.extend(adler32(data).to_be_bytes());
+    stream
+  }
+
+  #[test]
+  fn stored_block() {
+    assert_eq!(decode_zlib(&stored(b"russtv")).unwrap(), b"russtv");
+  }
+
+  #[test]
+  fn fixed_codes_block() {
+    // zlib.compress(b"hello hello hello", 9), which repeats with a back
+    // reference
+    let stream = [120, 218, 203, 72, 205, 201, 201, 87, 200, 64, 144, 0, 58, 46, 6, 125];
+    assert_eq!(decode_zlib(&stream).unwrap(), b"hello hello hello");
+  }
+
+  #[test]
+  fn dynamic_codes_round_trip() {
+    // Long enough that the encoder builds its own codes
+    let data: Vec<u8> = (0..20000u32).map(|i| (i * i % 251) as u8 ^ (i / 97) as u8).collect();
+    let stream = deflate::deflate_bytes_zlib(&data);
+    assert_eq!(stream[2] >> 1 & 3, 2);
+    assert_eq!(decode_zlib(&stream).unwrap(), data);
+  }
+
+  #[test]
+  fn bad_checksum_is_rejected() {
+    let mut stream = stored(b"russtv");
+    let last = stream.len() - 1;
+    stream[last] ^= 1;
+    assert_eq!(decode_zlib(&stream).unwrap_err().kind(), io::ErrorKind::InvalidData);
+  }
+}
@@ -0,0 +1,304 @@
+// Text and timestamp chunks stored in PNG files alongside the image
+
+use std::io;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::sstv::crypt;
+use crate::sstv::spec::{self, Spec};
+use crate::sstv::slant::SlantCorrection;
+
+
+// Keywords of the decode provenance. Software and Source are registered
+// PNG keywords, the rest are our own
+pub const KEY_SOFTWARE: &str = "Software";
+pub const KEY_SOURCE: &str = "Source";
+pub const KEY_MODE: &str = "SSTV Mode";
+pub const KEY_VIS: &str = "SSTV VIS";
+pub const KEY_START: &str = "SSTV Start";
+pub const KEY_SNR: &str = "SSTV SNR";
+pub const KEY_SLANT: &str = "SSTV Slant";
+pub const KEY_FSK_ID: &str = "SSTV FSK ID";
+
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PngMetadata {
+  // Keyword and text pairs, in the order they appear in the file
+  pub text: Vec<(String, String)>,
+  // Time the image was last modified, from the tIME chunk
+  pub time: Option<SystemTime>,
+}
+
+impl PngMetadata {
+  pub fn new() -> Self {
+    PngMetadata::default()
+  }
+
+
+  pub fn from_decode(mode: &Spec, source: &str) -> Self {
+    //"""Provenance of a decoded image: its mode and VIS code and the audio
+    //it came from, with the decode time in the tIME chunk"""
+    let mut metadata = PngMetadata::new();
+    metadata.set_text(KEY_SOFTWARE, &format!("russtv {}", env!("CARGO_PKG_VERSION")));
+    metadata.set_text(KEY_MODE, &mode.NAME);
+    if let Some(vis) = spec::vis_code(mode) {
+      metadata.set_text(KEY_VIS, &vis.to_string());
+    }
+    metadata.set_text(KEY_SOURCE, source);
+    metadata.time = Some(SystemTime::now());
+    metadata
+  }
+
+
+  pub fn add_text(&mut self, keyword: &str, text: &str) {
+    self.text.push((keyword.to_string(), text.to_string()));
+  }
+
+
+  pub fn set_text(&mut self, keyword: &str, text: &str) {
+    // Replaces any text already stored under the keyword
+    self.text.retain(|(key, _)| key != keyword);
+    self.add_text(keyword, text);
+  }
+
+
+  pub fn set_start(&mut self, start: Duration) {
+    // Time of the transmission from the start of the source recording
+    self.set_text(KEY_START, &format!("{:.3}", start.as_secs_f64()));
+  }
+
+
+  pub fn set_snr(&mut self, snr: f32) {
+    // Signal to noise ratio of the leader tone in dB
+    self.set_text(KEY_SNR, &format!("{:.1}", snr));
+  }
+
+
+  pub fn set_slant(&mut self, slant: &SlantCorrection) {
+    // Clock error corrected while decoding, in parts per million
+    self.set_text(KEY_SLANT, &format!("{:+.1}", slant.ppm));
+  }
+
+
+  pub fn get(&self, keyword: &str) -> Option<&str> {
+    // Text of the first chunk with the given keyword
+    self.text.iter()
+      .find(|(key, _)| key == keyword)
+      .map(|(_, text)| text.as_str())
+  }
+
+
+  pub fn set_fsk_id(&mut self, callsign: &str) {
+    // Callsign sent as an FSK ID after the image
+    self.set_text(KEY_FSK_ID, callsign);
+  }
+
+
+  pub fn mode_name(&self) -> Option<&str> {
+    self.get(KEY_MODE)
+  }
+
+
+  pub fn vis(&self) -> Option<usize> {
+    self.get(KEY_VIS)?.parse().ok()
+  }
+
+
+  pub fn source(&self) -> Option<&str> {
+    self.get(KEY_SOURCE)
+  }
+
+
+  pub fn start_offset(&self) -> Option<Duration> {
+    // Time of the transmission from the start of the source recording
+    let secs: f64 = self.get(KEY_START)?.parse().ok()?;
+    Duration::try_from_secs_f64(secs).ok()
+  }
+
+
+  pub fn snr(&self) -> Option<f32> {
+    self.get(KEY_SNR)?.parse().ok()
+  }
+
+
+  pub fn slant_ppm(&self) -> Option<f64> {
+    self.get(KEY_SLANT)?.parse().ok()
+  }
+
+
+  pub fn fsk_id(&self) -> Option<&str> {
+    self.get(KEY_FSK_ID)
+  }
+
+
+  pub(crate) fn to_chunks(&self) -> io::Result<Vec<([u8; 4], Vec<u8>)>> {
+    //"""Encodes the metadata as PNG chunks. Text that isn't plain ASCII
+    //goes in an iTXt chunk, as tEXt only holds Latin-1"""
+    let mut chunks: Vec<([u8; 4], Vec<u8>)> = Vec::new();
+
+    for (keyword, text) in &self.text {
+      // Keywords are 1-79 printable Latin-1 characters
+      if keyword.is_empty() || keyword.len() > 79 || !keyword.chars().all(|c| (' '..='~').contains(&c)) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("bad PNG keyword {:?}", keyword)));
+      }
+
+      let mut data = keyword.as_bytes().to_vec();
+      data.push(0);
+      if text.is_ascii() {
+        data.extend(text.as_bytes());
+        chunks.push((*b"tEXt", data));
+      } else {
+        // Uncompressed, with no language tag or translated keyword
+        data.extend([0, 0, 0, 0]);
+        data.extend(text.as_bytes());
+        chunks.push((*b"iTXt", data));
+      }
+    }
+
+    if let Some(time) = self.time {
+      let secs = time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+      let (year, month, day) = civil_from_days((secs / 86400) as i64);
+      let secs_of_day = secs % 86400;
+      let mut data = (year as u16).to_be_bytes().to_vec();
+      data.extend([
+        month as u8,
+        day as u8,
+        (secs_of_day / 3600) as u8,
+        (secs_of_day / 60 % 60) as u8,
+        (secs_of_day % 60) as u8,
+      ]);
+      chunks.push((*b"tIME", data));
+    }
+
+    Ok(chunks)
+  }
+
+
+  pub(crate) fn read_chunk(&mut self, tag: &[u8], data: &[u8]) -> io::Result<()> {
+    //"""Adds a tEXt, zTXt, iTXt or tIME chunk to the metadata, ignoring
+    //any other kind"""
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    let latin1 = |bytes: &[u8]| bytes.iter().map(|byte| *byte as char).collect::<String>();
+
+    match tag {
+      b"tEXt" | b"zTXt" | b"iTXt" => {
+        let split = data.iter().position(|byte| *byte == 0).ok_or_else(|| invalid("PNG text chunk without a keyword"))?;
+        let keyword = latin1(&data[..split]);
+        let rest = &data[split + 1..];
+
+        let text = match tag {
+          b"tEXt" => latin1(rest),
+          b"zTXt" => {
+            // Compression method byte, then zlib data
+            let compressed = rest.get(1..).ok_or_else(|| invalid("truncated zTXt chunk"))?;
+            latin1(&crypt::decode_data_zlib(compressed)?)
+          },
+          _ => {
+            // Compression flag and method, then the language tag and
+            // translated keyword, each ending in a null
+            if rest.len() < 2 {
+              return Err(invalid("truncated iTXt chunk"));
+            }
+            let compressed = rest[0] == 1;
+            let mut body = &rest[2..];
+            for _ in 0..2 {
+              let end = body.iter().position(|byte| *byte == 0).ok_or_else(|| invalid("truncated iTXt chunk"))?;
+              body = &body[end + 1..];
+            }
+            let bytes = if compressed { crypt::decode_data_zlib(body)? } else { body.to_vec() };
+            String::from_utf8(bytes).map_err(|_| invalid("iTXt chunk isn't UTF-8"))?
+          },
+        };
+        self.text.push((keyword, text));
+      },
+      b"tIME" => {
+        if data.len() != 7 {
+          return Err(invalid("bad tIME chunk"));
+        }
+        let year = u16::from_be_bytes([data[0], data[1]]) as i64;
+        let days = days_from_civil(year, data[2] as u32, data[3] as u32);
+        let secs = days * 86400 + data[4] as i64 * 3600 + data[5] as i64 * 60 + data[6] as i64;
+        // Times before 1970 can't be anything this wrote
+        self.time = Some(UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64));
+      },
+      _ => (),
+    }
+    Ok(())
+  }
+}
+
+
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+  //"""Converts days since 1970-01-01 to a (year, month, day) date in the
+  //proleptic Gregorian calendar"""
+  let days = days + 719468;
+  let era = days.div_euclid(146097);
+  let day_of_era = days.rem_euclid(146097);
+  let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+  let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+  // Months counted from March, so the leap day comes last
+  let month_index = (5 * day_of_year + 2) / 153;
+  let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+  let month = (if month_index < 10 { month_index + 3 } else { month_index - 9 }) as u32;
+  let year = year_of_era + era * 400 + (month <= 2) as i64;
+  (year, month, day)
+}
+
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+  // Inverse of civil_from_days
+  let year = if month <= 2 { year - 1 } else { year };
+  let era = year.div_euclid(400);
+  let year_of_era = year.rem_euclid(400);
+  let month_index = (if month > 2 { month - 3 } else { month + 9 }) as i64;
+  let day_of_year = (153 * month_index + 2) / 5 + day as i64 - 1;
+  let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+  era * 146097 + day_of_era - 719468
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn dates_convert_both_ways() {
+    assert_eq!(civil_from_days(0), (1970, 1, 1));
+    // 2024 is a leap year
+    assert_eq!(civil_from_days(19782), (2024, 2, 29));
+    for days in [-1, 59, 10956, 19782, 47482] {
+      let (year, month, day) = civil_from_days(days);
+      assert_eq!(days_from_civil(year, month, day), days);
+    }
+  }
+
+
+  #[test]
+  fn chunks_read_back() {
+    let mut metadata = PngMetadata::new();
+    metadata.set_text(KEY_MODE, "Martin 1");
+    metadata.set_fsk_id("ÆØÅ");
+    metadata.time = Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+
+    let chunks = metadata.to_chunks().unwrap();
+    let tags: Vec<&[u8; 4]> = chunks.iter().map(|(tag, _)| tag).collect();
+    assert_eq!(tags, vec![b"tEXt", b"iTXt", b"tIME"]);
+    // 2023-11-14 22:13:20 UTC
+    assert_eq!(chunks[2].1, vec![0x07, 0xE7, 11, 14, 22, 13, 20]);
+
+    let mut read = PngMetadata::new();
+    for (tag, data) in &chunks {
+      read.read_chunk(tag, data).unwrap();
+    }
+    assert_eq!(read, metadata);
+  }
+
+
+  #[test]
+  fn bad_keywords_are_refused() {
+    for keyword in ["", "tab\there", &"k".repeat(80)] {
+      let mut metadata = PngMetadata::new();
+      metadata.add_text(keyword, "text");
+      assert_eq!(metadata.to_chunks().unwrap_err().kind(), io::ErrorKind::InvalidInput, "{:?}", keyword);
+    }
+  }
+}
@@ -1,9 +1,34 @@
 mod decode;
+mod encode;
 mod spec;
 mod img;
+mod wav;
 mod crypt;
+mod inflate;
+mod meta;
+mod color;
+mod demod;
+mod stream;
+mod slant;
+mod resize;
+mod text;
+mod classify;
+mod fskid;
+mod error;
 
 
-pub use img::Image;
+pub use img::{Image, RGB};
+pub use meta::PngMetadata;
+pub use spec::{Spec, ColFmt, VIS_MAP, all_modes, mode_by_name, vis_code};
+pub use wav::{WavWriter, SampleFormat, read_wav_header};
+pub use error::SSTVError;
+pub use color::ColorRange;
 pub use decode::*;
+pub use encode::SSTVEncoder;
+pub use stream::{SSTVStream, StreamEvent, FollowReader};
+pub use slant::SlantCorrection;
+pub use resize::ResizeFilter;
+pub use text::{TextStyle, TextPosition, TextOverlay};
+pub use classify::ModeCandidate;
+pub use fskid::encode_fsk_id;
 pub use decode::calc_lum;
\ No newline at end of file
@@ -0,0 +1,268 @@
+// Resampling, cropping and padding of images, for fitting pictures to the
+// fixed resolution of a mode
+
+use crate::sstv::img::{Image, RGB};
+use crate::sstv::spec;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResizeFilter {
+  Bilinear,
+  // Sharper, but can ring slightly around hard edges
+  Lanczos3,
+}
+
+impl ResizeFilter {
+  fn radius(&self) -> f32 {
+    match self {
+      ResizeFilter::Bilinear => 1.0,
+      ResizeFilter::Lanczos3 => 3.0,
+    }
+  }
+
+  fn weight(&self, x: f32) -> f32 {
+    match self {
+      ResizeFilter::Bilinear => (1.0 - x.abs()).max(0.0),
+      ResizeFilter::Lanczos3 => {
+        if x.abs() >= 3.0 {
+          0.0
+        } else {
+          sinc(x) * sinc(x / 3.0)
+        }
+      },
+    }
+  }
+}
+
+
+fn sinc(x: f32) -> f32 {
+  if x.abs() < 1e-6 {
+    return 1.0;
+  }
+  let x = std::f32::consts::PI * x;
+  x.sin() / x
+}
+
+
+fn filter_weights(src: usize, dst: usize, filter: ResizeFilter) -> Vec<(usize, Vec<f32>)> {
+  //"""Works out, for each output pixel along one axis, the first source
+  //pixel it's taken from and the weights of it and those following"""
+  let scale = src as f32 / dst as f32;
+  // Stretch the filter when shrinking so every source pixel contributes
+  let support = scale.max(1.0);
+  let radius = filter.radius() * support;
+
+  (0..dst).map(|idx| {
+    let centre = (idx as f32 + 0.5) * scale;
+    let start = (centre - radius).floor().max(0.0) as usize;
+    let end = ((centre + radius).ceil() as usize).min(src);
+
+    let mut weights: Vec<f32> = (start..end)
+      .map(|pos| filter.weight((pos as f32 + 0.5 - centre) / support))
+      .collect();
+    let total: f32 = weights.iter().sum();
+    if total.abs() < 1e-6 {
+      // Fall back to the nearest pixel
+      let nearest = (centre as usize).min(src - 1);
+      return (nearest, vec![1.0]);
+    }
+    weights.iter_mut().for_each(|weight| *weight /= total);
+    (start, weights)
+  }).collect()
+}
+
+
+impl Image {
+  pub fn resize(&self, width: u32, height: u32, filter: ResizeFilter) -> Image {
+    //"""Resamples the image to the given size, ignoring its aspect ratio"""
+    let (src_width, src_height) = (self.width() as usize, self.height() as usize);
+    if width == 0 || height == 0 || src_width == 0 || src_height == 0 {
+      return Image::new(height, width);
+    }
+    let (dst_width, dst_height) = (width as usize, height as usize);
+    let data = self.data();
+
+    // Resample the rows first, then the columns of the result
+    let mut rows: Vec<f32> = vec![0.0; 3 * dst_width * src_height];
+    let columns = filter_weights(src_width, dst_width, filter);
+    for y in 0..src_height {
+      let src_row = &data[3 * src_width * y..3 * src_width * (y + 1)];
+      let dst_row = &mut rows[3 * dst_width * y..3 * dst_width * (y + 1)];
+      for (x, (start, weights)) in columns.iter().enumerate() {
+        for (offset, weight) in weights.iter().enumerate() {
+          let src = 3 * (start + offset);
+          for chan in 0..3 {
+            dst_row[3 * x + chan] += weight * src_row[src + chan] as f32;
+          }
+        }
+      }
+    }
+
+    let mut image = Image::new(height, width);
+    let lines = filter_weights(src_height, dst_height, filter);
+    let mut row: Vec<f32> = vec![0.0; 3 * dst_width];
+    for (y, (start, weights)) in lines.iter().enumerate() {
+      row.iter_mut().for_each(|value| *value = 0.0);
+      for (offset, weight) in weights.iter().enumerate() {
+        let src = 3 * dst_width * (start + offset);
+        for (value, src_value) in row.iter_mut().zip(&rows[src..src + 3 * dst_width]) {
+          *value += weight * src_value;
+        }
+      }
+      let pixels: Vec<u8> = row.iter().map(|value| value.round().clamp(0.0, 255.0) as u8).collect();
+      image.set_row(y as u32, &pixels);
+    }
+    image
+  }
+
+
+  pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Option<Image> {
+    //"""Cuts out a rectangle of the image, or None if it doesn't lie
+    //entirely within it"""
+    if x as u64 + width as u64 > self.width() as u64 || y as u64 + height as u64 > self.height() as u64 {
+      return None;
+    }
+
+    let mut image = Image::new(height, width);
+    let stride = 3 * self.width() as usize;
+    let start = 3 * x as usize;
+    for row in 0..height {
+      let offset = stride * (y + row) as usize + start;
+      image.set_row(row, &self.data()[offset..offset + 3 * width as usize]);
+    }
+    Some(image)
+  }
+
+
+  pub fn pad(&self, width: u32, height: u32, fill: RGB) -> Image {
+    //"""Centres the image on a canvas of the given size filled with the
+    //fill colour, cutting off whatever doesn't fit"""
+    let fill_row: Vec<u8> = (0..width).flat_map(|_| [fill.r, fill.g, fill.b]).collect();
+    let mut image = Image::new(height, width);
+    for row in 0..height {
+      image.set_row(row, &fill_row);
+    }
+
+    // Offsets of the source in the canvas, or of the canvas in the source
+    // when the source is bigger
+    let (dst_x, src_x) = centre_offsets(self.width(), width);
+    let (dst_y, src_y) = centre_offsets(self.height(), height);
+    let copy_width = self.width().min(width) as usize;
+    let copy_height = self.height().min(height);
+
+    let src_stride = 3 * self.width() as usize;
+    for row in 0..copy_height {
+      let src = src_stride * (src_y + row) as usize + 3 * src_x as usize;
+      let mut pixels = fill_row.clone();
+      pixels[3 * dst_x as usize..3 * (dst_x as usize + copy_width)]
+        .copy_from_slice(&self.data()[src..src + 3 * copy_width]);
+      image.set_row(dst_y + row, &pixels);
+    }
+    image
+  }
+
+
+  pub fn fit(&self, width: u32, height: u32, filter: ResizeFilter, fill: RGB) -> Image {
+    //"""Scales the image to fit within the given size keeping its aspect
+    //ratio, and pads the rest with the fill colour"""
+    if self.width() == 0 || self.height() == 0 {
+      return self.pad(width, height, fill);
+    }
+    let scale = f64::min(width as f64 / self.width() as f64, height as f64 / self.height() as f64);
+    let scaled_width = ((self.width() as f64 * scale).round() as u32).clamp(1, width.max(1));
+    let scaled_height = ((self.height() as f64 * scale).round() as u32).clamp(1, height.max(1));
+
+    if scaled_width == self.width() && scaled_height == self.height() {
+      return self.pad(width, height, fill);
+    }
+    self.resize(scaled_width, scaled_height, filter).pad(width, height, fill)
+  }
+
+
+  pub fn fit_to_mode(&self, mode: &spec::Spec, filter: ResizeFilter, fill: RGB) -> Image {
+    //"""Fits the image to the resolution sent by the mode"""
+    self.fit(mode.LINE_WIDTH as u32, mode.LINE_COUNT as u32, filter, fill)
+  }
+}
+
+
+fn centre_offsets(src: u32, dst: u32) -> (u32, u32) {
+  // (offset into the destination, offset into the source) that centre one
+  // length within the other
+  if src <= dst {
+    ((dst - src) / 2, 0)
+  } else {
+    (0, (src - dst) / 2)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const BLACK: RGB = RGB { r: 0, g: 0, b: 0 };
+
+
+  fn solid(width: u32, height: u32, color: (u8, u8, u8)) -> Image {
+    let mut image = Image::new(height, width);
+    for y in 0..height {
+      for x in 0..width {
+        image.set_pixel(x, y, color);
+      }
+    }
+    image
+  }
+
+
+  #[test]
+  fn bilinear_upscale_interpolates() {
+    let mut image = Image::new(1, 2);
+    image.set_pixel(1, 0, (255, 255, 255));
+    let scaled = image.resize(4, 1, ResizeFilter::Bilinear);
+    let reds: Vec<u8> = scaled.data().chunks(3).map(|px| px[0]).collect();
+    assert_eq!(reds, vec![0, 64, 191, 255]);
+  }
+
+
+  #[test]
+  fn solid_colour_survives_lanczos() {
+    let scaled = solid(64, 48, (10, 200, 90)).resize(20, 30, ResizeFilter::Lanczos3);
+    assert_eq!((scaled.width(), scaled.height()), (20, 30));
+    assert!(scaled.data().chunks(3).all(|px| px == [10, 200, 90]));
+  }
+
+
+  #[test]
+  fn fit_keeps_the_aspect_ratio() {
+    let red = RGB { r: 255, g: 0, b: 0 };
+    // 4:3 into Martin's 320x256 is scaled to 320x240 with 8 rows either side
+    let fitted = solid(640, 480, (255, 0, 0)).fit(320, 256, ResizeFilter::Lanczos3, BLACK);
+    assert_eq!((fitted.width(), fitted.height()), (320, 256));
+    for (y, expected) in [(0, BLACK), (7, BLACK), (8, red), (247, red), (248, BLACK)] {
+      assert_eq!(fitted.get_pixel(160, y), Some(expected), "row {}", y);
+    }
+
+    // Tall images get borders at the sides instead
+    let fitted = solid(100, 400, (255, 0, 0)).fit(320, 256, ResizeFilter::Bilinear, BLACK);
+    assert_eq!((fitted.width(), fitted.height()), (320, 256));
+    assert_eq!(fitted.get_pixel(0, 128), Some(BLACK));
+    assert_eq!(fitted.get_pixel(160, 128), Some(red));
+  }
+
+
+  #[test]
+  fn crop_and_pad() {
+    let mut image = Image::new(40, 60);
+    image.set_pixel(10, 20, (1, 2, 3));
+    let cropped = image.crop(10, 20, 30, 15).unwrap();
+    assert_eq!((cropped.width(), cropped.height()), (30, 15));
+    assert_eq!(cropped.get_pixel(0, 0), Some(RGB { r: 1, g: 2, b: 3 }));
+    assert!(image.crop(40, 0, 30, 10).is_none());
+
+    // Padding to a smaller size keeps the centre
+    let padded = image.pad(40, 20, BLACK);
+    assert_eq!((padded.width(), padded.height()), (40, 20));
+    assert_eq!(padded.get_pixel(0, 10), Some(RGB { r: 1, g: 2, b: 3 }));
+  }
+}
@@ -0,0 +1,93 @@
+// Estimates the real sample rate of a recording from its sync pulses
+
+
+// Clock errors beyond this are treated as a failed fit rather than slant
+const MAX_PPM: f64 = 20000.0;
+// Rounds of discarding syncs that are far from the fitted line
+const REJECT_ROUNDS: usize = 3;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SlantCorrection {
+  // Sample where the sync pulse of the first line starts
+  pub first_sync: f64,
+  // Samples between successive sync pulses
+  pub line_samples: f64,
+  // Sample rate the recording was actually made at
+  pub sample_rate: f64,
+  // Clock error of the recording in parts per million
+  pub ppm: f64,
+}
+
+impl SlantCorrection {
+  pub fn sync_start(&self, line: usize) -> f64 {
+    // Where the fitted line timing places the sync pulse of a line
+    self.first_sync + line as f64 * self.line_samples
+  }
+}
+
+
+fn least_squares(points: &[(f64, f64)]) -> Option<(f64, f64)> {
+  //"""Fits y = a + b * x, returning (a, b)"""
+  let n = points.len() as f64;
+  let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+  let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+
+  let mut cov = 0.0;
+  let mut var = 0.0;
+  for (x, y) in points {
+    cov += (x - mean_x) * (y - mean_y);
+    var += (x - mean_x) * (x - mean_x);
+  }
+
+  if var == 0.0 {
+    return None;
+  }
+  let slope = cov / var;
+  Some((mean_y - slope * mean_x, slope))
+}
+
+
+pub fn fit_syncs(syncs: &[(usize, usize)], nominal_line: f64, sample_rate: u32, tolerance: f64) -> Option<SlantCorrection> {
+  //"""Fits a straight line through the (line, sample) positions of the sync
+  //pulses found in an image. Syncs further than the tolerance in samples
+  //from the fit are dropped as misdetections and the line refitted"""
+  let mut points: Vec<(f64, f64)> = syncs.iter()
+    .map(|(line, sample)| (*line as f64, *sample as f64))
+    .collect();
+
+  // Too few syncs to say anything about the whole image
+  let min_points = 8;
+  if points.len() < min_points {
+    return None;
+  }
+
+  let mut fit = least_squares(&points)?;
+  for _ in 0..REJECT_ROUNDS {
+    let (intercept, slope) = fit;
+    let before = points.len();
+    points.retain(|(x, y)| (y - (intercept + slope * x)).abs() <= tolerance);
+
+    if points.len() < min_points {
+      return None;
+    }
+    if points.len() == before {
+      break;
+    }
+    fit = least_squares(&points)?;
+  }
+
+  let (first_sync, line_samples) = fit;
+  let ratio = line_samples / nominal_line;
+  let ppm = (ratio - 1.0) * 1e6;
+  if ppm.abs() > MAX_PPM {
+    return None;
+  }
+
+  Some(SlantCorrection {
+    first_sync,
+    line_samples,
+    sample_rate: sample_rate as f64 * ratio,
+    ppm,
+  })
+}
@@ -1,4 +1,4 @@
-#![allow(dead_code, non_snake_case)]
+#![allow(dead_code, non_snake_case, clippy::new_ret_no_self, clippy::upper_case_acronyms)]
 // """Constants for SSTV specification and each supported mode"""
 
 #[derive(Debug, Clone, PartialEq)]
@@ -38,15 +38,77 @@ pub struct Spec {
   pub HAS_START_SYNC: bool,
   pub HAS_HALF_SCAN: bool,
   pub HAS_ALT_SCAN: bool,
+  pub HAS_DUAL_LINE: bool,
+  // Each line sent is drawn as two rows of the image
+  pub HAS_DOUBLE_HEIGHT: bool,
+}
+
+impl Spec {
+  pub fn sync_lines(&self) -> usize {
+    // Number of sync pulses sent for the image
+    if self.HAS_DUAL_LINE || self.HAS_DOUBLE_HEIGHT {
+      // PD modes send two lines after each sync pulse, and Martin 3/4 and
+      // Scottie 3/4 send half the lines of the image
+      self.LINE_COUNT / 2
+    } else {
+      self.LINE_COUNT
+    }
+  }
+
+  pub fn image_time(&self) -> f32 {
+    // Length of the transmission after the VIS in seconds
+    let mut image_time = self.LINE_TIME * self.sync_lines() as f32;
+    if self.HAS_START_SYNC {
+      image_time += self.SYNC_PULSE;
+    }
+    image_time
+  }
 }
 
 pub struct M1;
 pub struct M2;
+pub struct M3;
+pub struct M4;
 pub struct S1;
 pub struct S2;
+pub struct S3;
+pub struct S4;
 pub struct SDX;
+pub struct R12;
+pub struct R24;
 pub struct R36;
 pub struct R72;
+pub struct R8BW;
+pub struct R12BW;
+pub struct R24BW;
+pub struct R36BW;
+pub struct PD50;
+pub struct PD90;
+pub struct PD120;
+pub struct PD160;
+pub struct PD180;
+pub struct PD240;
+pub struct PD290;
+pub struct SC230;
+pub struct SC260;
+pub struct SC2120;
+pub struct SC2180;
+pub struct P3;
+pub struct P5;
+pub struct P7;
+pub struct MR73;
+pub struct MR90;
+pub struct MR115;
+pub struct MR140;
+pub struct MR175;
+pub struct MP73;
+pub struct MP115;
+pub struct MP140;
+pub struct MP175;
+pub struct ML180;
+pub struct ML240;
+pub struct ML280;
+pub struct ML320;
 
 impl M1 {
   pub fn new() -> Spec {
@@ -56,7 +118,7 @@ impl M1 {
     let sync_porch: f32 = 0.000572;
     let line_width: usize = 320;
     let chan_time = sep_pulse + scan_time;
-    let chan_offsets: Vec<f32> = (0..3).into_iter().map(|i| (sync_pulse + sync_porch) + chan_time * (i) as f32).collect();
+    let chan_offsets: Vec<f32> = (0..3).map(|i| (sync_pulse + sync_porch) + chan_time * (i) as f32).collect();
     Spec {
       NAME: "Martin 1".to_string(),
       COLOR: ColFmt::GBR,
@@ -84,6 +146,8 @@ impl M1 {
       HAS_START_SYNC: false,
       HAS_HALF_SCAN: false,
       HAS_ALT_SCAN: false,
+      HAS_DUAL_LINE: false,
+      HAS_DOUBLE_HEIGHT: false,
     }
     }
   }
@@ -98,7 +162,7 @@ impl M2 {
     let sync_porch: f32 = 0.000572;
     let line_width: usize = 320;
     let chan_time = sep_pulse + scan_time;
-    let chan_offsets: Vec<f32> = (0..3).into_iter().map(|i| (sync_pulse + sync_porch) + chan_time * (i) as f32).collect();
+    let chan_offsets: Vec<f32> = (0..3).map(|i| (sync_pulse + sync_porch) + chan_time * (i) as f32).collect();
 
     Spec {
       NAME: "Martin 2".to_string(),
@@ -127,6 +191,30 @@ impl M2 {
       HAS_START_SYNC: false,
       HAS_HALF_SCAN: false,
       HAS_ALT_SCAN: false,
+      HAS_DUAL_LINE: false,
+      HAS_DOUBLE_HEIGHT: false,
+    }
+  }
+}
+
+impl M3 {
+  pub fn new() -> Spec {
+    // Martin 1 timing with 128 lines
+    Spec {
+      NAME: "Martin 3".to_string(),
+      HAS_DOUBLE_HEIGHT: true,
+      ..M1::new()
+    }
+  }
+}
+
+impl M4 {
+  pub fn new() -> Spec {
+    // Martin 2 timing with 128 lines
+    Spec {
+      NAME: "Martin 4".to_string(),
+      HAS_DOUBLE_HEIGHT: true,
+      ..M2::new()
     }
   }
 }
@@ -173,6 +261,8 @@ impl S1 {
       HAS_START_SYNC: true,
       HAS_HALF_SCAN: false,
       HAS_ALT_SCAN: false,
+      HAS_DUAL_LINE: false,
+      HAS_DOUBLE_HEIGHT: false,
     }
   }
 }
@@ -220,12 +310,41 @@ impl S2 {
       HAS_START_SYNC: true,
       HAS_HALF_SCAN: false,
       HAS_ALT_SCAN: false,
+      HAS_DUAL_LINE: false,
+      HAS_DOUBLE_HEIGHT: false,
     }
   }
 }
 
 
 
+impl S3 {
+  pub fn new() -> Spec {
+    // Scottie 1 timing with 128 lines
+    Spec {
+      NAME: "Scottie 3".to_string(),
+      HAS_DOUBLE_HEIGHT: true,
+      ..S1::new()
+    }
+  }
+}
+
+impl S4 {
+  pub fn new() -> Spec {
+    // Scottie 2 timing with 128 lines
+    Spec {
+      NAME: "Scottie 4".to_string(),
+      HAS_DOUBLE_HEIGHT: true,
+      ..S2::new()
+    }
+  }
+}
+
+
+
+// Scottie DX2 isn't supported: there's no published VIS code or line timing
+// for it that a decoder could be checked against
+
 impl SDX {
   pub fn new() -> Spec {
     let sync_pulse: f32 = 0.009000;
@@ -268,129 +387,645 @@ impl SDX {
       HAS_START_SYNC: true,
       HAS_HALF_SCAN: false,
       HAS_ALT_SCAN: false,
+      HAS_DUAL_LINE: false,
+      HAS_DOUBLE_HEIGHT: false,
     }
   }
 }
 
 
 
+fn r36_spec(name: &str, scan_time: f32, line_width: usize, line_count: usize, window_factor: f32) -> Spec {
+  // Robot 36 style modes send Y then a half-length chroma scan that
+  // alternates between R-Y on even lines and B-Y on odd lines
+  let SCAN_TIME: f32 = scan_time;
+  let LINE_WIDTH: usize  = line_width;
+  let HALF_SCAN_TIME: f32 = scan_time / 2.0;
+  let SYNC_PORCH: f32 = 0.003000;
+  let SEP_PULSE: f32 = 0.004500;
+  let SEP_PORCH: f32 = 0.001500;
+  let CHAN_TIME: f32 = SEP_PULSE + SCAN_TIME;
+  let SYNC_PULSE: f32 = 0.009000;
+  let PIXEL_TIME: f32 = SCAN_TIME / LINE_WIDTH as f32;
+  let CHAN_OFFSETS: Vec<f32> = vec! [
+    SYNC_PULSE + SYNC_PORCH,
+    SYNC_PULSE + SYNC_PORCH + CHAN_TIME + SEP_PORCH
+  ];
+
+  let LINE_TIME: f32 = CHAN_OFFSETS[1] + HALF_SCAN_TIME;
+
+  Spec {
+    NAME: name.to_string(),
+
+    COLOR: ColFmt::YUV,
+    LINE_WIDTH,
+    LINE_COUNT: line_count,
+    SCAN_TIME,
+    HALF_SCAN_TIME,
+    SYNC_PULSE,
+    SYNC_PORCH,
+    SEP_PULSE,
+    SEP_PORCH,
+
+    CHAN_COUNT: 2,
+    CHAN_SYNC: 0,
+    CHAN_TIME,
+    HALF_CHAN_TIME: 0.0,
+
+    CHAN_OFFSETS,
+
+    LINE_TIME,
+    PIXEL_TIME,
+    HALF_PIXEL_TIME: HALF_SCAN_TIME / LINE_WIDTH as f32,
+    WINDOW_FACTOR: window_factor,
+
+    HAS_START_SYNC: false,
+    HAS_HALF_SCAN: true,
+    HAS_ALT_SCAN: true,
+    HAS_DUAL_LINE: false,
+    HAS_DOUBLE_HEIGHT: false,
+  }
+}
+
+
+fn r72_spec(name: &str, scan_time: f32, line_width: usize, line_count: usize, window_factor: f32) -> Spec {
+  // Robot 72 style modes send Y then half-length R-Y and B-Y scans on
+  // every line
+  let SCAN_TIME: f32 = scan_time;
+  let LINE_WIDTH: usize  = line_width;
+  let HALF_SCAN_TIME: f32 = scan_time / 2.0;
+  let SYNC_PORCH: f32 = 0.003000;
+  let SEP_PULSE: f32 = 0.004500;
+  let SEP_PORCH: f32 = 0.001500;
+  let CHAN_TIME: f32 = SEP_PULSE + SCAN_TIME;
+  let SYNC_PULSE: f32 = 0.009000;
+  let HALF_CHAN_TIME: f32 = SEP_PULSE + HALF_SCAN_TIME;
+  let CHAN_OFFSETS: Vec<f32> = vec! [
+    SYNC_PULSE + SYNC_PORCH,
+    SYNC_PULSE + SYNC_PORCH + CHAN_TIME + SEP_PORCH,
+    SYNC_PULSE + SYNC_PORCH + CHAN_TIME + SEP_PORCH + HALF_CHAN_TIME + SEP_PORCH
+  ];
+
+  let LINE_TIME: f32 = CHAN_OFFSETS[2] + HALF_SCAN_TIME;
+
+  Spec {
+    NAME: name.to_string(),
+
+    COLOR: ColFmt::YUV,
+    LINE_WIDTH,
+    LINE_COUNT: line_count,
+    SCAN_TIME,
+    HALF_SCAN_TIME,
+    SYNC_PULSE,
+    SYNC_PORCH,
+    SEP_PULSE,
+    SEP_PORCH,
+
+    CHAN_COUNT: 3,
+    CHAN_SYNC: 0,
+    CHAN_TIME,
+    HALF_CHAN_TIME,
+
+    CHAN_OFFSETS,
+
+    LINE_TIME,
+    PIXEL_TIME: SCAN_TIME / LINE_WIDTH as f32,
+    HALF_PIXEL_TIME: HALF_SCAN_TIME / LINE_WIDTH as f32,
+    WINDOW_FACTOR: window_factor,
+
+    HAS_START_SYNC: false,
+    HAS_HALF_SCAN: true,
+    HAS_ALT_SCAN: false,
+    HAS_DUAL_LINE: false,
+    HAS_DOUBLE_HEIGHT: false,
+  }
+}
+
+
+fn robot_bw_spec(name: &str, scan_time: f32, line_width: usize, line_count: usize, window_factor: f32) -> Spec {
+  // Robot black and white modes send a single Y scan after each sync pulse
+  let sync_pulse: f32 = 0.007000;
+  let sync_porch: f32 = 0.003000;
+
+  Spec {
+    NAME: name.to_string(),
+
+    COLOR: ColFmt::BW,
+    LINE_WIDTH: line_width,
+    LINE_COUNT: line_count,
+    SCAN_TIME: scan_time,
+    HALF_SCAN_TIME: 0.0,
+    SYNC_PULSE: sync_pulse,
+    SYNC_PORCH: sync_porch,
+    SEP_PULSE: 0.0,
+    SEP_PORCH: 0.0,
+
+    CHAN_COUNT: 1,
+    CHAN_SYNC: 0,
+    CHAN_TIME: scan_time,
+    HALF_CHAN_TIME: 0.0,
+
+    CHAN_OFFSETS: vec![sync_pulse + sync_porch],
+
+    LINE_TIME: sync_pulse + sync_porch + scan_time,
+    PIXEL_TIME: scan_time / line_width as f32,
+    HALF_PIXEL_TIME: 0.0,
+    WINDOW_FACTOR: window_factor,
+
+    HAS_START_SYNC: false,
+    HAS_HALF_SCAN: false,
+    HAS_ALT_SCAN: false,
+    HAS_DUAL_LINE: false,
+    HAS_DOUBLE_HEIGHT: false,
+  }
+}
+
+
+impl R12 {
+  pub fn new() -> Spec {
+    r36_spec("Robot 12", 0.060000, 160, 120, 5.60)
+  }
+}
+
+impl R24 {
+  pub fn new() -> Spec {
+    r72_spec("Robot 24", 0.088000, 160, 120, 3.82)
+  }
+}
+
 impl R36 {
   pub fn new() -> Spec {
-    let SCAN_TIME: f32 = 0.088000;
-    let LINE_WIDTH: usize  = 320;
-    let HALF_SCAN_TIME: f32 = 0.044000;
-    let SYNC_PORCH: f32 = 0.003000;
-    let SEP_PULSE: f32 = 0.004500;
-    let SEP_PORCH: f32 = 0.001500;
-    let CHAN_TIME: f32 = SEP_PULSE + SCAN_TIME;
-    let SYNC_PULSE: f32 = 0.009000;
-    let PIXEL_TIME: f32 = SCAN_TIME / LINE_WIDTH as f32;
-    let CHAN_OFFSETS: Vec<f32> = vec! [
-      SYNC_PULSE + SYNC_PORCH,
-      SYNC_PULSE + SYNC_PORCH + CHAN_TIME + SEP_PORCH
-    ];
+    r36_spec("Robot 36", 0.088000, 320, 240, 7.70)
+  }
+}
 
-    let LINE_TIME: f32 = CHAN_OFFSETS[1] + HALF_SCAN_TIME;
+impl R72 {
+  pub fn new() -> Spec {
+    r72_spec("Robot 72", 0.138000, 320, 240, 4.88)
+  }
+}
 
-    Spec {
-      NAME: "Robot 36".to_string(),
-
-      COLOR: ColFmt::YUV,
-      LINE_WIDTH,
-      LINE_COUNT: 240,
-      SCAN_TIME,
-      HALF_SCAN_TIME,
-      SYNC_PULSE,
-      SYNC_PORCH,
-      SEP_PULSE,
-      SEP_PORCH,
-
-      CHAN_COUNT: 2,
-      CHAN_SYNC: 0,
-      CHAN_TIME,
-      HALF_CHAN_TIME: 0.0,
+impl R8BW {
+  pub fn new() -> Spec {
+    robot_bw_spec("Robot 8 BW", 0.056667, 160, 120, 3.02)
+  }
+}
 
-      CHAN_OFFSETS,
+impl R12BW {
+  pub fn new() -> Spec {
+    robot_bw_spec("Robot 12 BW", 0.090000, 160, 120, 1.90)
+  }
+}
 
-      LINE_TIME,
-      PIXEL_TIME,
-      HALF_PIXEL_TIME: HALF_SCAN_TIME / LINE_WIDTH as f32,
-      WINDOW_FACTOR: 7.70,
+impl R24BW {
+  pub fn new() -> Spec {
+    robot_bw_spec("Robot 24 BW", 0.090000, 320, 240, 3.81)
+  }
+}
 
-      HAS_START_SYNC: false,
-      HAS_HALF_SCAN: true,
-      HAS_ALT_SCAN: true,
-    }
+impl R36BW {
+  pub fn new() -> Spec {
+    robot_bw_spec("Robot 36 BW", 0.140000, 320, 240, 2.45)
   }
 }
 
 
-impl R72 {
+
+fn pd_spec(name: &str, scan_time: f32, line_width: usize, line_count: usize, window_factor: f32) -> Spec {
+  // PD modes send Y of an even line, R-Y and B-Y shared by the line pair,
+  // then Y of the odd line, all after a single sync pulse
+  let sync_pulse: f32 = 0.020000;
+  let sync_porch: f32 = 0.002080;
+  let chan_offsets: Vec<f32> = (0..4).map(|i| sync_pulse + sync_porch + scan_time * i as f32).collect();
+
+  Spec {
+    NAME: name.to_string(),
+
+    COLOR: ColFmt::YUV,
+    LINE_WIDTH: line_width,
+    LINE_COUNT: line_count,
+    SCAN_TIME: scan_time,
+    HALF_SCAN_TIME: 0.0,
+    SYNC_PULSE: sync_pulse,
+    SYNC_PORCH: sync_porch,
+    SEP_PULSE: 0.0,
+    SEP_PORCH: 0.0,
+
+    CHAN_COUNT: 4,
+    CHAN_SYNC: 0,
+    CHAN_TIME: scan_time,
+    HALF_CHAN_TIME: 0.0,
+
+    CHAN_OFFSETS: chan_offsets,
+
+    LINE_TIME: sync_pulse + sync_porch + 4.0 * scan_time,
+    PIXEL_TIME: scan_time / line_width as f32,
+    HALF_PIXEL_TIME: 0.0,
+    WINDOW_FACTOR: window_factor,
+
+    HAS_START_SYNC: false,
+    HAS_HALF_SCAN: false,
+    HAS_ALT_SCAN: false,
+    HAS_DUAL_LINE: true,
+    HAS_DOUBLE_HEIGHT: false,
+  }
+}
+
+
+impl PD50 {
   pub fn new() -> Spec {
-    let SCAN_TIME: f32 = 0.138000;
-    let LINE_WIDTH: usize  = 320;
-    let HALF_SCAN_TIME: f32 = 0.069000;
-    let SYNC_PORCH: f32 = 0.003000;
-    let SEP_PULSE: f32 = 0.004500;
-    let SEP_PORCH: f32 = 0.001500;
-    let CHAN_TIME: f32 = SEP_PULSE + SCAN_TIME;
-    let SYNC_PULSE: f32 = 0.009000;
-    let HALF_CHAN_TIME: f32 = SEP_PULSE + HALF_SCAN_TIME;
-    let CHAN_OFFSETS: Vec<f32> = vec! [
-      SYNC_PULSE + SYNC_PORCH,
-      SYNC_PULSE + SYNC_PORCH + CHAN_TIME + SEP_PORCH,
-      SYNC_PULSE + SYNC_PORCH + CHAN_TIME + SEP_PORCH + HALF_CHAN_TIME + SEP_PORCH
-    ];
+    pd_spec("PD50", 0.091520, 320, 256, 3.74)
+  }
+}
 
-    let LINE_TIME: f32 = CHAN_OFFSETS[2] + HALF_SCAN_TIME;
+impl PD90 {
+  pub fn new() -> Spec {
+    pd_spec("PD90", 0.170240, 320, 256, 2.01)
+  }
+}
 
-    Spec {
-      NAME: "Robot 72".to_string(),
-
-      COLOR: ColFmt::YUV,
-      LINE_WIDTH,
-      LINE_COUNT: 240,
-      SCAN_TIME,
-      HALF_SCAN_TIME,
-      SYNC_PULSE,
-      SYNC_PORCH,
-      SEP_PULSE,
-      SEP_PORCH,
+impl PD120 {
+  pub fn new() -> Spec {
+    pd_spec("PD120", 0.121600, 640, 496, 5.63)
+  }
+}
 
-      CHAN_COUNT: 3,
-      CHAN_SYNC: 0,
-      CHAN_TIME,
-      HALF_CHAN_TIME,
+impl PD160 {
+  pub fn new() -> Spec {
+    pd_spec("PD160", 0.195584, 512, 400, 2.80)
+  }
+}
+
+impl PD180 {
+  pub fn new() -> Spec {
+    pd_spec("PD180", 0.183040, 640, 496, 3.74)
+  }
+}
+
+impl PD240 {
+  pub fn new() -> Spec {
+    pd_spec("PD240", 0.244480, 640, 496, 2.80)
+  }
+}
+
+impl PD290 {
+  pub fn new() -> Spec {
+    pd_spec("PD290", 0.228800, 800, 616, 3.74)
+  }
+}
 
-      CHAN_OFFSETS,
 
-      LINE_TIME,
-      PIXEL_TIME: SCAN_TIME / LINE_WIDTH as f32,
-      HALF_PIXEL_TIME: HALF_SCAN_TIME / LINE_WIDTH as f32,
-      WINDOW_FACTOR: 4.88,
 
-      HAS_START_SYNC: false,
-      HAS_HALF_SCAN: true,
-      HAS_ALT_SCAN: false,
+fn sc2_spec(name: &str, scan_time: f32, window_factor: f32) -> Spec {
+  // Wraase SC2 modes send the red, green and blue scans back to back after
+  // a single sync pulse
+  let sync_pulse: f32 = 0.0055225;
+  let sync_porch: f32 = 0.000500;
+  let line_width: usize = 320;
+  let chan_offsets: Vec<f32> = (0..3).map(|i| sync_pulse + sync_porch + scan_time * i as f32).collect();
+
+  Spec {
+    NAME: name.to_string(),
+
+    COLOR: ColFmt::RGB,
+    LINE_WIDTH: line_width,
+    LINE_COUNT: 256,
+    SCAN_TIME: scan_time,
+    HALF_SCAN_TIME: 0.0,
+    SYNC_PULSE: sync_pulse,
+    SYNC_PORCH: sync_porch,
+    SEP_PULSE: 0.0,
+    SEP_PORCH: 0.0,
+
+    CHAN_COUNT: 3,
+    CHAN_SYNC: 0,
+    CHAN_TIME: scan_time,
+    HALF_CHAN_TIME: 0.0,
+
+    CHAN_OFFSETS: chan_offsets,
+
+    LINE_TIME: sync_pulse + sync_porch + 3.0 * scan_time,
+    PIXEL_TIME: scan_time / line_width as f32,
+    HALF_PIXEL_TIME: 0.0,
+    WINDOW_FACTOR: window_factor,
+
+    HAS_START_SYNC: false,
+    HAS_HALF_SCAN: false,
+    HAS_ALT_SCAN: false,
+    HAS_DUAL_LINE: false,
+    HAS_DOUBLE_HEIGHT: false,
+  }
+}
+
+
+impl SC230 {
+  pub fn new() -> Spec {
+    // SC2-60 timing with 128 lines
+    Spec {
+      NAME: "SC2-30".to_string(),
+      HAS_DOUBLE_HEIGHT: true,
+      ..SC260::new()
     }
   }
 }
 
+impl SC260 {
+  pub fn new() -> Spec {
+    sc2_spec("SC2-60", 0.078120, 4.38)
+  }
+}
+
+impl SC2120 {
+  pub fn new() -> Spec {
+    sc2_spec("SC2-120", 0.156500, 2.19)
+  }
+}
+
+impl SC2180 {
+  pub fn new() -> Spec {
+    sc2_spec("SC2-180", 0.235000, 1.46)
+  }
+}
+
+
+
+fn pasokon_spec(name: &str, unit: f32, window_factor: f32) -> Spec {
+  // Pasokon modes are timed in units of one pixel: a 25 unit sync pulse,
+  // then the red, green and blue scans of 640 units, each surrounded by
+  // 5 unit gaps. Pixels are short enough that a window wider than one
+  // pixel smears the picture
+  let sync_pulse: f32 = 25.0 * unit;
+  let sync_porch: f32 = 5.0 * unit;
+  let sep_pulse: f32 = 5.0 * unit;
+  let line_width: usize = 640;
+  let scan_time: f32 = line_width as f32 * unit;
+  let chan_time = sep_pulse + scan_time;
+  let chan_offsets: Vec<f32> = (0..3).map(|i| sync_pulse + sync_porch + chan_time * i as f32).collect();
+
+  Spec {
+    NAME: name.to_string(),
+
+    COLOR: ColFmt::RGB,
+    LINE_WIDTH: line_width,
+    LINE_COUNT: 496,
+    SCAN_TIME: scan_time,
+    HALF_SCAN_TIME: 0.0,
+    SYNC_PULSE: sync_pulse,
+    SYNC_PORCH: sync_porch,
+    SEP_PULSE: sep_pulse,
+    SEP_PORCH: 0.0,
+
+    CHAN_COUNT: 3,
+    CHAN_SYNC: 0,
+    CHAN_TIME: chan_time,
+    HALF_CHAN_TIME: 0.0,
+
+    CHAN_OFFSETS: chan_offsets,
+
+    LINE_TIME: sync_pulse + sync_porch + 3.0 * chan_time,
+    PIXEL_TIME: unit,
+    HALF_PIXEL_TIME: 0.0,
+    WINDOW_FACTOR: window_factor,
+
+    HAS_START_SYNC: false,
+    HAS_HALF_SCAN: false,
+    HAS_ALT_SCAN: false,
+    HAS_DUAL_LINE: false,
+    HAS_DOUBLE_HEIGHT: false,
+  }
+}
+
+
+impl P3 {
+  pub fn new() -> Spec {
+    pasokon_spec("P3", 1.0 / 4800.0, 1.00)
+  }
+}
+
+impl P5 {
+  pub fn new() -> Spec {
+    pasokon_spec("P5", 1.0 / 3200.0, 1.00)
+  }
+}
+
+impl P7 {
+  pub fn new() -> Spec {
+    pasokon_spec("P7", 1.0 / 2400.0, 1.00)
+  }
+}
+
+
+
+fn mr_spec(name: &str, scan_time: f32, line_width: usize, line_count: usize, window_factor: f32) -> Spec {
+  // MMSSTV MR and ML modes send Y then half-length R-Y and B-Y scans on
+  // every line, like Robot 72 with shorter gaps. Windows wider than a
+  // pixel or two blur the 640 pixel wide ML modes
+  let sync_pulse: f32 = 0.009000;
+  let sync_porch: f32 = 0.001000;
+  let sep_pulse: f32 = 0.000100;
+  let half_scan_time: f32 = scan_time / 2.0;
+  let chan_time: f32 = sep_pulse + scan_time;
+  let half_chan_time: f32 = sep_pulse + half_scan_time;
+  let chan_offsets: Vec<f32> = vec![
+    sync_pulse + sync_porch,
+    sync_pulse + sync_porch + chan_time,
+    sync_pulse + sync_porch + chan_time + half_chan_time
+  ];
+
+  Spec {
+    NAME: name.to_string(),
+
+    COLOR: ColFmt::YUV,
+    LINE_WIDTH: line_width,
+    LINE_COUNT: line_count,
+    SCAN_TIME: scan_time,
+    HALF_SCAN_TIME: half_scan_time,
+    SYNC_PULSE: sync_pulse,
+    SYNC_PORCH: sync_porch,
+    SEP_PULSE: sep_pulse,
+    SEP_PORCH: 0.0,
+
+    CHAN_COUNT: 3,
+    CHAN_SYNC: 0,
+    CHAN_TIME: chan_time,
+    HALF_CHAN_TIME: half_chan_time,
+
+    LINE_TIME: chan_offsets[2] + half_scan_time,
+    CHAN_OFFSETS: chan_offsets,
+
+    PIXEL_TIME: scan_time / line_width as f32,
+    HALF_PIXEL_TIME: half_scan_time / line_width as f32,
+    WINDOW_FACTOR: window_factor,
+
+    HAS_START_SYNC: false,
+    HAS_HALF_SCAN: true,
+    HAS_ALT_SCAN: false,
+    HAS_DUAL_LINE: false,
+    HAS_DOUBLE_HEIGHT: false,
+  }
+}
+
+
+fn mp_spec(name: &str, scan_time: f32, window_factor: f32) -> Spec {
+  // MMSSTV MP modes are laid out like PD modes, with a shorter sync pulse
+  // and porch
+  let sync_pulse: f32 = 0.009000;
+  let sync_porch: f32 = 0.001000;
+
+  Spec {
+    SYNC_PULSE: sync_pulse,
+    SYNC_PORCH: sync_porch,
+    CHAN_OFFSETS: (0..4).map(|i| sync_pulse + sync_porch + scan_time * i as f32).collect(),
+    LINE_TIME: sync_pulse + sync_porch + 4.0 * scan_time,
+    ..pd_spec(name, scan_time, 320, 256, window_factor)
+  }
+}
+
+
+impl MR73 {
+  pub fn new() -> Spec {
+    mr_spec("MR73", 0.138000, 320, 256, 1.50)
+  }
+}
+
+impl MR90 {
+  pub fn new() -> Spec {
+    mr_spec("MR90", 0.171000, 320, 256, 1.50)
+  }
+}
+
+impl MR115 {
+  pub fn new() -> Spec {
+    mr_spec("MR115", 0.220000, 320, 256, 1.50)
+  }
+}
+
+impl MR140 {
+  pub fn new() -> Spec {
+    mr_spec("MR140", 0.269000, 320, 256, 1.50)
+  }
+}
+
+impl MR175 {
+  pub fn new() -> Spec {
+    mr_spec("MR175", 0.337000, 320, 256, 1.50)
+  }
+}
+
+impl MP73 {
+  pub fn new() -> Spec {
+    mp_spec("MP73", 0.140000, 1.50)
+  }
+}
+
+impl MP115 {
+  pub fn new() -> Spec {
+    mp_spec("MP115", 0.223000, 1.50)
+  }
+}
+
+impl MP140 {
+  pub fn new() -> Spec {
+    mp_spec("MP140", 0.270000, 1.50)
+  }
+}
+
+impl MP175 {
+  pub fn new() -> Spec {
+    mp_spec("MP175", 0.340000, 1.50)
+  }
+}
+
+impl ML180 {
+  pub fn new() -> Spec {
+    mr_spec("ML180", 0.176500, 640, 496, 1.00)
+  }
+}
+
+impl ML240 {
+  pub fn new() -> Spec {
+    mr_spec("ML240", 0.236500, 640, 496, 1.00)
+  }
+}
+
+impl ML280 {
+  pub fn new() -> Spec {
+    mr_spec("ML280", 0.277500, 640, 496, 1.00)
+  }
+}
+
+impl ML320 {
+  pub fn new() -> Spec {
+    mr_spec("ML320", 0.317500, 640, 496, 1.00)
+  }
+}
+
 
 
 pub fn VIS_MAP(vis: usize) -> Result<Spec, String> {
   match vis {
+    0 => Ok(R12::new()),
+    2 => Ok(R8BW::new()),
+    4 => Ok(R24::new()),
+    6 => Ok(R12BW::new()),
     8 => Ok(R36::new()),
+    10 => Ok(R24BW::new()),
     12 => Ok(R72::new()),
+    14 => Ok(R36BW::new()),
+    32 => Ok(M4::new()),
+    36 => Ok(M3::new()),
     40 => Ok(M2::new()),
     44 => Ok(M1::new()),
+    48 => Ok(S4::new()),
+    51 => Ok(SC230::new()),
+    52 => Ok(S3::new()),
+    55 => Ok(SC2180::new()),
     56 => Ok(S2::new()),
+    59 => Ok(SC260::new()),
     60 => Ok(S1::new()),
+    63 => Ok(SC2120::new()),
     76 => Ok(SDX::new()),
+    93 => Ok(PD50::new()),
+    94 => Ok(PD290::new()),
+    95 => Ok(PD120::new()),
+    96 => Ok(PD180::new()),
+    97 => Ok(PD240::new()),
+    98 => Ok(PD160::new()),
+    99 => Ok(PD90::new()),
+    113 => Ok(P3::new()),
+    114 => Ok(P5::new()),
+    115 => Ok(P7::new()),
+    // MMSSTV's extended VIS codes, as the 0x23 prefix in the low byte and
+    // the second byte sent, parity bit included, in the high byte
+    0x2523 => Ok(MP73::new()),
+    0x2923 => Ok(MP115::new()),
+    0x2A23 => Ok(MP140::new()),
+    0x2C23 => Ok(MP175::new()),
+    0x4523 => Ok(MR73::new()),
+    0x4623 => Ok(MR90::new()),
+    0x4923 => Ok(MR115::new()),
+    0x4A23 => Ok(MR140::new()),
+    0x4C23 => Ok(MR175::new()),
+    0x8523 => Ok(ML180::new()),
+    0x8623 => Ok(ML240::new()),
+    0x8923 => Ok(ML280::new()),
+    0x8A23 => Ok(ML320::new()),
     _ => Err("Not found".to_owned())
   }
 }
 
+pub const VIS_CODES: &[usize] = &[
+  0, 2, 4, 6, 8, 10, 12, 14, 32, 36, 40, 44, 48, 51, 52, 55, 56, 59, 60, 63, 76, 93, 94, 95, 96, 97, 98, 99, 113, 114, 115,
+  0x2523, 0x2923, 0x2A23, 0x2C23, 0x4523, 0x4623, 0x4923, 0x4A23, 0x4C23, 0x8523, 0x8623, 0x8923, 0x8A23,
+];
+
+pub fn vis_code(mode: &Spec) -> Option<usize> {
+  // Reverse lookup of VIS_MAP
+  VIS_CODES.iter()
+    .find(|vis| VIS_MAP(**vis).is_ok_and(|m| m.NAME == mode.NAME))
+    .copied()
+}
+
 pub const BREAK_OFFSET: f32 = 0.300;
 pub const LEADER_OFFSET: f32 = 0.010 + BREAK_OFFSET;
 pub const VIS_START_OFFSET: f32 = 0.300 + LEADER_OFFSET;
@@ -399,3 +1034,50 @@ pub const HDR_SIZE: f32 = 0.030 + VIS_START_OFFSET;
 pub const HDR_WINDOW_SIZE: f32 = 0.010;
 
 pub const VIS_BIT_SIZE: f32 = 0.030;
+// First byte of MMSSTV's extended 16 bit VIS, which fails the parity check
+// of a normal VIS
+pub const EXT_VIS_PREFIX: usize = 0x23;
+
+
+pub fn vis_bit_count(vis: usize) -> usize {
+  // Bits sent for the VIS code, including parity but not the start and
+  // stop bits
+  if vis > 0xFF { 16 } else { 8 }
+}
+
+pub fn vis_time(mode: &Spec) -> f32 {
+  // Length of the VIS of the mode after the start bit, up to the image
+  let bits = vis_code(mode).map_or(8, vis_bit_count);
+  VIS_BIT_SIZE * (bits + 1) as f32
+}
+
+
+pub fn all_modes() -> Vec<Spec> {
+  VIS_CODES.iter().filter_map(|vis| VIS_MAP(*vis).ok()).collect()
+}
+
+fn short_name(name: &str) -> String {
+  // "Martin 1" -> "m1", "Scottie DX" -> "sdx", "PD50" -> "pd50"
+  let mut words = name.split_whitespace();
+  let first = words.next().unwrap_or("");
+  let rest: String = words.collect();
+  if rest.is_empty() {
+    first.to_lowercase()
+  } else {
+    format!("{}{}", &first[..1], rest).to_lowercase()
+  }
+}
+
+pub fn mode_by_name(name: &str) -> Option<Spec> {
+  //"""Looks up a mode by VIS number, full name or short name, ignoring case
+  //and spaces"""
+  if let Ok(vis) = name.trim().parse::<usize>() {
+    return VIS_MAP(vis).ok();
+  }
+
+  let wanted: String = name.split_whitespace().collect::<String>().to_lowercase();
+  all_modes().into_iter().find(|mode| {
+    let full: String = mode.NAME.split_whitespace().collect::<String>().to_lowercase();
+    full == wanted || short_name(&mode.NAME) == wanted
+  })
+}
@@ -0,0 +1,333 @@
+// Push-based decoding of live audio
+
+use std::io::{self, Read};
+use std::thread;
+use std::time::Duration;
+
+use crate::sstv::spec;
+use crate::sstv::img;
+use crate::sstv::wav::SampleFormat;
+use crate::sstv::error::SSTVError;
+use crate::sstv::demod::FmDemodulator;
+use crate::sstv::decode::{SSTVSetup, SSTVDecoder, LineCursor, PixelVec};
+
+
+// Consecutive lines without a sync pulse before giving up on an image
+const MAX_MISSED_SYNCS: usize = 8;
+
+
+pub enum StreamEvent {
+  HeaderDetected(spec::Spec),
+  // Image row and its packed RGB pixels
+  LineDecoded(usize, Vec<u8>),
+  ImageComplete(img::Image),
+  // Image cut short by the end of the audio or the loss of the signal, with
+  // the rows never received left black
+  ImageTruncated(img::Image),
+  SignalLost,
+}
+
+
+struct Reception {
+  decoder: SSTVDecoder,
+  cursor: Option<LineCursor>,
+  image_data: PixelVec,
+  missed_syncs: usize,
+}
+
+enum StreamState {
+  Searching(SSTVSetup),
+  Receiving(Box<Reception>),
+}
+
+
+pub struct SSTVStream {
+  sample_rate: u32,
+  channels: u16,
+  // Channel of the next interleaved sample
+  channel_pos: u16,
+  demod: FmDemodulator,
+  // Demodulator output still to be dropped to line up with the audio
+  delay_left: usize,
+  state: StreamState,
+}
+
+// Create a decoder that audio can be fed into as it arrives
+impl SSTVStream {
+  pub fn new(sample_rate: u32, channels: u16) -> Self {
+    let demod = FmDemodulator::new(sample_rate);
+    let delay_left = demod.delay();
+    SSTVStream {
+      sample_rate,
+      channels: channels.max(1),
+      channel_pos: 0,
+      demod,
+      delay_left,
+      state: StreamState::Searching(SSTVSetup::from_track(Vec::new(), sample_rate)),
+    }
+  }
+
+
+  pub fn push_i16(&mut self, chunk: &[i16]) -> Vec<StreamEvent> {
+    // Only the first channel is decoded
+    let mut mono: Vec<i16> = Vec::with_capacity(chunk.len() / self.channels as usize + 1);
+    for sample in chunk {
+      if self.channel_pos == 0 {
+        mono.push(*sample);
+      }
+      self.channel_pos = (self.channel_pos + 1) % self.channels;
+    }
+
+    let freqs = self.demod.process(&mono);
+    let mut events: Vec<StreamEvent> = Vec::new();
+    self.push_track(freqs, &mut events);
+    events
+  }
+
+
+  pub fn push_f32(&mut self, chunk: &[f32]) -> Vec<StreamEvent> {
+    let pcm: Vec<i16> = chunk.iter()
+      .map(|s| (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)
+      .collect();
+    self.push_i16(&pcm)
+  }
+
+
+  pub fn finish(&mut self) -> Vec<StreamEvent> {
+    //"""Decodes what is left of an image still being received once the audio
+    //has ended, and resets the stream for new audio"""
+    let mut events: Vec<StreamEvent> = Vec::new();
+
+    // The last samples are still in the demodulator's filter
+    let tail = self.demod.process(&vec![0; self.demod.delay()]);
+    self.push_track(tail, &mut events);
+
+    if let StreamState::Receiving(reception) = &mut self.state {
+      SSTVStream::receive(reception, self.sample_rate, true, &mut events);
+    }
+
+    self.channel_pos = 0;
+    self.demod = FmDemodulator::new(self.sample_rate);
+    self.delay_left = self.demod.delay();
+    self.state = StreamState::Searching(SSTVSetup::from_track(Vec::new(), self.sample_rate));
+    events
+  }
+
+
+  pub fn read_pcm<R: Read, F: FnMut(StreamEvent)>(&mut self, reader: &mut R, format: SampleFormat, mut on_event: F) -> io::Result<()> {
+    //"""Feeds little-endian PCM from the reader until it runs out, then
+    //finishes the image being received"""
+    let sample_size = match format {
+      SampleFormat::PCM16 => 2,
+      SampleFormat::Float32 => 4,
+    };
+    let mut buffer = vec![0u8; 4096 * sample_size];
+    // Bytes of a sample split across two reads
+    let mut pending: Vec<u8> = Vec::new();
+
+    loop {
+      let count = match reader.read(&mut buffer) {
+        Ok(0) => {
+          for event in self.finish() {
+            on_event(event);
+          }
+          return Ok(());
+        },
+        Ok(count) => count,
+        Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+        Err(err) => return Err(err),
+      };
+      pending.extend(&buffer[..count]);
+      let whole = pending.len() - pending.len() % sample_size;
+
+      let events = match format {
+        SampleFormat::PCM16 => {
+          let chunk: Vec<i16> = pending[..whole].chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+          self.push_i16(&chunk)
+        },
+        SampleFormat::Float32 => {
+          let chunk: Vec<f32> = pending[..whole].chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+          self.push_f32(&chunk)
+        },
+      };
+      pending.drain(..whole);
+
+      for event in events {
+        on_event(event);
+      }
+    }
+  }
+
+
+  fn push_track(&mut self, mut freqs: Vec<f32>, events: &mut Vec<StreamEvent>) {
+    // Drop the demodulator's delay so the track lines up with the audio
+    let skip = self.delay_left.min(freqs.len());
+    self.delay_left -= skip;
+    freqs.drain(..skip);
+
+    match &mut self.state {
+      StreamState::Searching(setup) => setup.track.extend(freqs),
+      StreamState::Receiving(reception) => reception.decoder.track.extend(freqs),
+    }
+
+    // Keep going while a state change leaves more audio to look at
+    while self.step(events) {}
+  }
+
+
+  fn step(&mut self, events: &mut Vec<StreamEvent>) -> bool {
+    //"""Advances the state machine as far as the buffered audio allows,
+    //returning true if the state changed"""
+    let next = match &mut self.state {
+      StreamState::Searching(setup) => SSTVStream::search(setup, self.sample_rate, events),
+      StreamState::Receiving(reception) => SSTVStream::receive(reception, self.sample_rate, false, events),
+    };
+
+    match next {
+      Some(state) => {
+        self.state = state;
+        true
+      },
+      None => false,
+    }
+  }
+
+
+  fn search(setup: &mut SSTVSetup, sample_rate: u32, events: &mut Vec<StreamEvent>) -> Option<StreamState> {
+    let header_size = (spec::HDR_SIZE * sample_rate as f32) as usize;
+    let jump_size = ((0.002 * sample_rate as f32) as usize).max(1);
+
+    let header_end = match setup.find_header() {
+      Ok(header_end) => header_end,
+      Err(_) => {
+        // Only the last header's worth of audio could still hold the start
+        // of one. Dropping whole search steps keeps the search positions the
+        // same as for a complete recording
+        let stale = setup.track.len().saturating_sub(header_size);
+        let stale = stale - stale % jump_size;
+        setup.track.drain(..stale);
+        return None;
+      },
+    };
+
+    let freq_offset = setup.measure_offset(header_end);
+    match setup.decode_vis(header_end, freq_offset) {
+      Ok(mode) => {
+        events.push(StreamEvent::HeaderDetected(mode.clone()));
+        let track = std::mem::take(&mut setup.track);
+        let decoder = SSTVDecoder::new(mode, sample_rate, track, header_end, freq_offset);
+        let image_data = decoder.new_image_data();
+        Some(StreamState::Receiving(Box::new(Reception {
+          decoder,
+          cursor: None,
+          image_data,
+          missed_syncs: 0,
+        })))
+      },
+      // Wait for the rest of the VIS
+      Err(SSTVError::TruncatedVis) => None,
+      Err(_) => {
+        // Not a usable header, so look beyond it
+        setup.track.drain(..header_end);
+        Some(StreamState::Searching(SSTVSetup::from_track(std::mem::take(&mut setup.track), sample_rate)))
+      },
+    }
+  }
+
+
+  fn receive(reception: &mut Reception, sample_rate: u32, at_end: bool, events: &mut Vec<StreamEvent>) -> Option<StreamState> {
+    //"""Decodes the lines the buffered audio covers. At the end of the audio
+    //every line left is decoded against what there is"""
+    let decoder = &reception.decoder;
+    let mode = decoder.mode();
+
+    // Audio needed past the start of a line before it can be decoded
+    // without running out part way through
+    let margin = if at_end {
+      0
+    } else {
+      ((2.0 * mode.LINE_TIME + 2.0 * mode.SYNC_PULSE) * sample_rate as f32) as usize
+    };
+
+    if reception.cursor.is_none() {
+      let image_start = decoder.image_start();
+      if decoder.track.len() < image_start + margin {
+        return None;
+      }
+      reception.cursor = decoder.start_image(image_start).ok();
+    }
+
+    let cursor = match &mut reception.cursor {
+      Some(cursor) => cursor,
+      None => return Some(SSTVStream::resume_search(decoder, decoder.image_start(), sample_rate)),
+    };
+
+    while decoder.track.len() >= cursor.seq_start + margin {
+      let line = cursor.line;
+      if !decoder.decode_line(cursor, &mut reception.image_data) {
+        break;
+      }
+
+      if decoder.has_sync(cursor) {
+        reception.missed_syncs = 0;
+      } else {
+        reception.missed_syncs += 1;
+        if reception.missed_syncs >= MAX_MISSED_SYNCS {
+          events.push(StreamEvent::SignalLost);
+          events.push(StreamEvent::ImageTruncated(decoder.draw_image(&reception.image_data)));
+          return Some(SSTVStream::resume_search(decoder, cursor.sync_start, sample_rate));
+        }
+      }
+
+      for y in decoder.completed_rows(line) {
+        events.push(StreamEvent::LineDecoded(y, decoder.draw_row(&reception.image_data, y)));
+      }
+
+      if cursor.line == mode.sync_lines() {
+        events.push(StreamEvent::ImageComplete(decoder.draw_image(&reception.image_data)));
+        let image_end = cursor.sync_start + (mode.LINE_TIME * sample_rate as f32) as usize;
+        return Some(SSTVStream::resume_search(decoder, image_end, sample_rate));
+      }
+    }
+
+    if at_end && cursor.line > 0 {
+      events.push(StreamEvent::ImageTruncated(decoder.draw_image(&reception.image_data)));
+    }
+    None
+  }
+
+
+  fn resume_search(decoder: &SSTVDecoder, from: usize, sample_rate: u32) -> StreamState {
+    let from = from.min(decoder.track.len());
+    StreamState::Searching(SSTVSetup::from_track(decoder.track[from..].to_vec(), sample_rate))
+  }
+}
+
+
+pub struct FollowReader<R: Read> {
+  inner: R,
+  poll: Duration,
+}
+
+// Reader for a file that is still being written, which waits for more data
+// instead of reporting the end of the file
+impl<R: Read> FollowReader<R> {
+  pub fn new(inner: R) -> Self {
+    FollowReader { inner, poll: Duration::from_millis(250) }
+  }
+}
+
+impl<R: Read> Read for FollowReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+      match self.inner.read(buf)? {
+        0 if !buf.is_empty() => thread::sleep(self.poll),
+        count => return Ok(count),
+      }
+    }
+  }
+}
@@ -0,0 +1,372 @@
+// Text drawn onto images with a built-in bitmap font, for stamping station
+// identification on pictures before they're sent
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::sstv::img::{Image, RGB};
+use crate::sstv::meta;
+use crate::sstv::spec::{self, Spec};
+
+
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+// Space left after each character and below each line, before scaling
+const CHAR_SPACING: u32 = 1;
+const LINE_SPACING: u32 = 1;
+// Gap between positioned text and the edge of the image, before scaling
+const EDGE_MARGIN: u32 = 2;
+
+// Columns of each printable ASCII character from space to tilde, with the
+// top row in the lowest bit
+const FONT: [[u8; 5]; 95] = [
+  [0x00, 0x00, 0x00, 0x00, 0x00], [0x00, 0x00, 0x5F, 0x00, 0x00], [0x00, 0x07, 0x00, 0x07, 0x00],
+  [0x14, 0x7F, 0x14, 0x7F, 0x14], [0x24, 0x2A, 0x7F, 0x2A, 0x12], [0x23, 0x13, 0x08, 0x64, 0x62],
+  [0x36, 0x49, 0x55, 0x22, 0x50], [0x00, 0x05, 0x03, 0x00, 0x00], [0x00, 0x1C, 0x22, 0x41, 0x00],
+  [0x00, 0x41, 0x22, 0x1C, 0x00], [0x08, 0x2A, 0x1C, 0x2A, 0x08], [0x08, 0x08, 0x3E, 0x08, 0x08],
+  [0x00, 0x50, 0x30, 0x00, 0x00], [0x08, 0x08, 0x08, 0x08, 0x08], [0x00, 0x60, 0x60, 0x00, 0x00],
+  [0x20, 0x10, 0x08, 0x04, 0x02], [0x3E, 0x51, 0x49, 0x45, 0x3E], [0x00, 0x42, 0x7F, 0x40, 0x00],
+  [0x42, 0x61, 0x51, 0x49, 0x46], [0x21, 0x41, 0x45, 0x4B, 0x31], [0x18, 0x14, 0x12, 0x7F, 0x10],
+  [0x27, 0x45, 0x45, 0x45, 0x39], [0x3C, 0x4A, 0x49, 0x49, 0x30], [0x01, 0x71, 0x09, 0x05, 0x03],
+  [0x36, 0x49, 0x49, 0x49, 0x36], [0x06, 0x49, 0x49, 0x29, 0x1E], [0x00, 0x36, 0x36, 0x00, 0x00],
+  [0x00, 0x56, 0x36, 0x00, 0x00], [0x08, 0x14, 0x22, 0x41, 0x00], [0x14, 0x14, 0x14, 0x14, 0x14],
+  [0x00, 0x41, 0x22, 0x14, 0x08], [0x02, 0x01, 0x51, 0x09, 0x06], [0x32, 0x49, 0x79, 0x41, 0x3E],
+  [0x7E, 0x11, 0x11, 0x11, 0x7E], [0x7F, 0x49, 0x49, 0x49, 0x36], [0x3E, 0x41, 0x41, 0x41, 0x22],
+  [0x7F, 0x41, 0x41, 0x22, 0x1C], [0x7F, 0x49, 0x49, 0x49, 0x41], [0x7F, 0x09, 0x09, 0x09, 0x01],
+  [0x3E, 0x41, 0x49, 0x49, 0x7A], [0x7F, 0x08, 0x08, 0x08, 0x7F], [0x00, 0x41, 0x7F, 0x41, 0x00],
+  [0x20, 0x40, 0x41, 0x3F, 0x01], [0x7F, 0x08, 0x14, 0x22, 0x41], [0x7F, 0x40, 0x40, 0x40, 0x40],
+  [0x7F, 0x02, 0x0C, 0x02, 0x7F], [0x7F, 0x04, 0x08, 0x10, 0x7F], [0x3E, 0x41, 0x41, 0x41, 0x3E],
+  [0x7F, 0x09, 0x09, 0x09, 0x06], [0x3E, 0x41, 0x51, 0x21, 0x5E], [0x7F, 0x09, 0x19, 0x29, 0x46],
+  [0x46, 0x49, 0x49, 0x49, 0x31], [0x01, 0x01, 0x7F, 0x01, 0x01], [0x3F, 0x40, 0x40, 0x40, 0x3F],
+  [0x1F, 0x20, 0x40, 0x20, 0x1F], [0x3F, 0x40, 0x38, 0x40, 0x3F], [0x63, 0x14, 0x08, 0x14, 0x63],
+  [0x07, 0x08, 0x70, 0x08, 0x07], [0x61, 0x51, 0x49, 0x45, 0x43], [0x00, 0x7F, 0x41, 0x41, 0x00],
+  [0x02, 0x04, 0x08, 0x10, 0x20], [0x00, 0x41, 0x41, 0x7F, 0x00], [0x04, 0x02, 0x01, 0x02, 0x04],
+  [0x40, 0x40, 0x40, 0x40, 0x40], [0x00, 0x01, 0x02, 0x04, 0x00], [0x20, 0x54, 0x54, 0x54, 0x78],
+  [0x7F, 0x48, 0x44, 0x44, 0x38], [0x38, 0x44, 0x44, 0x44, 0x20], [0x38, 0x44, 0x44, 0x48, 0x7F],
+  [0x38, 0x54, 0x54, 0x54, 0x18], [0x08, 0x7E, 0x09, 0x01, 0x02], [0x0C, 0x52, 0x52, 0x52, 0x3E],
+  [0x7F, 0x08, 0x04, 0x04, 0x78], [0x00, 0x44, 0x7D, 0x40, 0x00], [0x20, 0x40, 0x44, 0x3D, 0x00],
+  [0x7F, 0x10, 0x28, 0x44, 0x00], [0x00, 0x41, 0x7F, 0x40, 0x00], [0x7C, 0x04, 0x18, 0x04, 0x78],
+  [0x7C, 0x08, 0x04, 0x04, 0x78], [0x38, 0x44, 0x44, 0x44, 0x38], [0x7C, 0x14, 0x14, 0x14, 0x08],
+  [0x08, 0x14, 0x14, 0x18, 0x7C], [0x7C, 0x08, 0x04, 0x04, 0x08], [0x48, 0x54, 0x54, 0x54, 0x20],
+  [0x04, 0x3F, 0x44, 0x40, 0x20], [0x3C, 0x40, 0x40, 0x20, 0x7C], [0x1C, 0x20, 0x40, 0x20, 0x1C],
+  [0x3C, 0x40, 0x30, 0x40, 0x3C], [0x44, 0x28, 0x10, 0x28, 0x44], [0x0C, 0x50, 0x50, 0x50, 0x3C],
+  [0x44, 0x64, 0x54, 0x4C, 0x44], [0x00, 0x08, 0x36, 0x41, 0x00], [0x00, 0x00, 0x7F, 0x00, 0x00],
+  [0x00, 0x41, 0x36, 0x08, 0x00], [0x08, 0x04, 0x08, 0x10, 0x08],
+];
+
+
+fn glyph(c: char) -> &'static [u8; 5] {
+  // Characters the font doesn't have are drawn as a question mark
+  match c {
+    ' '..='~' => &FONT[c as usize - ' ' as usize],
+    _ => &FONT['?' as usize - ' ' as usize],
+  }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextPosition {
+  // Top left corner of the text, which may lie partly off the image
+  At(i32, i32),
+  TopLeft,
+  Top,
+  TopRight,
+  BottomLeft,
+  Bottom,
+  BottomRight,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextStyle {
+  pub color: RGB,
+  // Each font pixel is drawn as a square this many pixels across
+  pub scale: u32,
+  // Drawn around each character so the text shows on any picture
+  pub outline: Option<RGB>,
+  // Filled box behind the text
+  pub background: Option<RGB>,
+  // Space between the text and the edge of its background box
+  pub padding: u32,
+}
+
+impl Default for TextStyle {
+  fn default() -> Self {
+    TextStyle {
+      color: RGB { r: 255, g: 255, b: 255 },
+      scale: 2,
+      outline: Some(RGB { r: 0, g: 0, b: 0 }),
+      background: None,
+      padding: 2,
+    }
+  }
+}
+
+impl TextStyle {
+  fn outline_width(&self) -> u32 {
+    if self.outline.is_some() { (self.scale / 2).max(1) } else { 0 }
+  }
+
+  fn border(&self) -> u32 {
+    // Space taken around the glyphs by the outline or background box
+    if self.background.is_some() {
+      self.padding + self.outline_width()
+    } else {
+      self.outline_width()
+    }
+  }
+}
+
+
+impl Image {
+  pub fn text_size(text: &str, style: &TextStyle) -> (u32, u32) {
+    //"""Width and height of the text when drawn in the style, including its
+    //outline and background box"""
+    let scale = style.scale.max(1);
+    let columns = text.lines().map(|line| line.chars().count() as u32).max().unwrap_or(0);
+    let rows = text.lines().count() as u32;
+    if columns == 0 {
+      return (0, 0);
+    }
+    let width = scale * (columns * (GLYPH_WIDTH + CHAR_SPACING) - CHAR_SPACING);
+    let height = scale * (rows * (GLYPH_HEIGHT + LINE_SPACING) - LINE_SPACING);
+    (width + 2 * style.border(), height + 2 * style.border())
+  }
+
+
+  pub fn draw_text(&mut self, text: &str, position: TextPosition, style: &TextStyle) {
+    //"""Draws text in the built-in font, one line per line of text. Anything
+    //falling outside the image is cut off"""
+    let (width, height) = Image::text_size(text, style);
+    if width == 0 {
+      return;
+    }
+    let (x, y) = self.text_origin(position, width, height, style.scale.max(1));
+
+    if let Some(background) = style.background {
+      self.fill_rect(x, y, width, height, background);
+    }
+
+    let scale = style.scale.max(1) as i64;
+    let border = style.border() as i64;
+    // Outline everything first so it can't cover a neighbouring character
+    let passes = [(style.outline, style.outline_width() as i64), (Some(style.color), 0)];
+    for (color, spread) in passes {
+      let Some(color) = color else { continue };
+      for (row, line) in text.lines().enumerate() {
+        let top = y + border + row as i64 * scale * (GLYPH_HEIGHT + LINE_SPACING) as i64;
+        for (col, c) in line.chars().enumerate() {
+          let left = x + border + col as i64 * scale * (GLYPH_WIDTH + CHAR_SPACING) as i64;
+          self.draw_glyph(c, left, top, scale, spread, color);
+        }
+      }
+    }
+  }
+
+
+  fn text_origin(&self, position: TextPosition, width: u32, height: u32, scale: u32) -> (i64, i64) {
+    // Top left corner of text of the given size placed on the image
+    let margin = (scale * EDGE_MARGIN) as i64;
+    let (width, height) = (width as i64, height as i64);
+    let left = margin;
+    let centre = (self.width() as i64 - width) / 2;
+    let right = self.width() as i64 - width - margin;
+    let top = margin;
+    let bottom = self.height() as i64 - height - margin;
+
+    match position {
+      TextPosition::At(x, y) => (x as i64, y as i64),
+      TextPosition::TopLeft => (left, top),
+      TextPosition::Top => (centre, top),
+      TextPosition::TopRight => (right, top),
+      TextPosition::BottomLeft => (left, bottom),
+      TextPosition::Bottom => (centre, bottom),
+      TextPosition::BottomRight => (right, bottom),
+    }
+  }
+
+
+  fn draw_glyph(&mut self, c: char, left: i64, top: i64, scale: i64, spread: i64, color: RGB) {
+    // Each font pixel is a scale-sized square, grown by spread on each side
+    for (col, bits) in glyph(c).iter().enumerate() {
+      for row in 0..GLYPH_HEIGHT as i64 {
+        if bits >> row & 1 == 0 {
+          continue;
+        }
+        let x = left + col as i64 * scale - spread;
+        let y = top + row * scale - spread;
+        let size = (scale + 2 * spread) as u32;
+        self.fill_rect(x, y, size, size, color);
+      }
+    }
+  }
+
+
+  fn fill_rect(&mut self, x: i64, y: i64, width: u32, height: u32, color: RGB) {
+    // Clipped to the image
+    let x_end = (x + width as i64).min(self.width() as i64);
+    let y_end = (y + height as i64).min(self.height() as i64);
+    for py in y.max(0)..y_end {
+      for px in x.max(0)..x_end {
+        self.set_pixel(px as u32, py as u32, (color.r, color.g, color.b));
+      }
+    }
+  }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextOverlay {
+  // Text with fields in braces, e.g. "{call} {date} {mode}"
+  pub template: String,
+  pub position: TextPosition,
+  pub style: TextStyle,
+  // Values of fields the encoder can't fill in itself, such as call
+  pub fields: Vec<(String, String)>,
+}
+
+impl TextOverlay {
+  pub fn new(template: &str) -> Self {
+    TextOverlay {
+      template: template.to_string(),
+      position: TextPosition::TopLeft,
+      style: TextStyle::default(),
+      fields: Vec::new(),
+    }
+  }
+
+
+  pub fn set_field(&mut self, name: &str, value: &str) {
+    self.fields.retain(|(key, _)| key != name);
+    self.fields.push((name.to_string(), value.to_string()));
+  }
+
+
+  pub fn render(&self, mode: &Spec, time: SystemTime) -> String {
+    //"""Fills in the template. mode, vis, date (YYYY-MM-DD) and time
+    //(HH:MMZ) come from the transmission, in UTC; other fields from those
+    //set. Unknown fields are left as they are and {{ or }} give a brace"""
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+    let (year, month, day) = meta::civil_from_days((secs / 86400) as i64);
+    let secs_of_day = secs % 86400;
+
+    let lookup = |name: &str| -> Option<String> {
+      if let Some((_, value)) = self.fields.iter().find(|(key, _)| key == name) {
+        return Some(value.clone());
+      }
+      match name {
+        "mode" => Some(mode.NAME.clone()),
+        "vis" => spec::vis_code(mode).map(|vis| vis.to_string()),
+        "date" => Some(format!("{:04}-{:02}-{:02}", year, month, day)),
+        "time" => Some(format!("{:02}:{:02}Z", secs_of_day / 3600, secs_of_day / 60 % 60)),
+        _ => None,
+      }
+    };
+
+    let mut text = String::new();
+    let mut rest = self.template.as_str();
+    while let Some(idx) = rest.find(['{', '}']) {
+      text.push_str(&rest[..idx]);
+      rest = &rest[idx..];
+      if rest.starts_with("{{") || rest.starts_with("}}") {
+        text.push_str(&rest[..1]);
+        rest = &rest[2..];
+        continue;
+      }
+      let field = if rest.starts_with('{') { rest.find('}') } else { None };
+      match field.and_then(|end| lookup(&rest[1..end]).map(|value| (end, value))) {
+        Some((end, value)) => {
+          text.push_str(&value);
+          rest = &rest[end + 1..];
+        },
+        None => {
+          text.push_str(&rest[..1]);
+          rest = &rest[1..];
+        },
+      }
+    }
+    text.push_str(rest);
+    text
+  }
+
+
+  pub fn apply(&self, image: &mut Image, mode: &Spec, time: SystemTime) {
+    // Draws the filled in template on the image
+    image.draw_text(&self.render(mode, time), self.position, &self.style);
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // 2023-11-14 22:13:20 UTC
+  fn time() -> SystemTime {
+    UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000)
+  }
+
+
+  #[test]
+  fn render_fills_in_fields() {
+    let mode = spec::mode_by_name("Martin 1").unwrap();
+    let mut overlay = TextOverlay::new("{call} {mode} VIS {vis} {date} {time}");
+    overlay.set_field("call", "N0CALL");
+    assert_eq!(overlay.render(&mode, time()), "N0CALL Martin 1 VIS 44 2023-11-14 22:13Z");
+
+    // Fields set by hand take the place of the built-in ones
+    let mut overlay = TextOverlay::new("{mode}");
+    overlay.set_field("mode", "M1");
+    assert_eq!(overlay.render(&mode, time()), "M1");
+  }
+
+
+  #[test]
+  fn render_escapes_and_unknown_fields() {
+    let mode = spec::mode_by_name("Robot 36").unwrap();
+    for (template, expected) in [
+      ("{{mode}}", "{mode}"),
+      ("{{{mode}}}", "{Robot 36}"),
+      ("{nope} {mode}", "{nope} Robot 36"),
+      ("unclosed {mode", "unclosed {mode"),
+      ("stray } brace", "stray } brace"),
+      ("", ""),
+    ] {
+      assert_eq!(TextOverlay::new(template).render(&mode, time()), expected, "{:?}", template);
+    }
+  }
+
+
+  #[test]
+  fn text_origin_places_text_by_the_edges() {
+    let image = Image::new(256, 320);
+    let style = TextStyle::default();
+    // Two characters at scale 2 with a 1 pixel outline
+    let (width, height) = Image::text_size("AB", &style);
+    assert_eq!((width, height), (24, 16));
+
+    for (position, expected) in [
+      (TextPosition::TopLeft, (4, 4)),
+      (TextPosition::Top, (148, 4)),
+      (TextPosition::TopRight, (292, 4)),
+      (TextPosition::BottomLeft, (4, 236)),
+      (TextPosition::Bottom, (148, 236)),
+      (TextPosition::BottomRight, (292, 236)),
+      (TextPosition::At(-5, 3), (-5, 3)),
+    ] {
+      assert_eq!(image.text_origin(position, width, height, style.scale), expected, "{:?}", position);
+    }
+  }
+
+
+  #[test]
+  fn glyphs_are_drawn_at_the_origin() {
+    let mut image = Image::new(64, 64);
+    let style = TextStyle { outline: None, ..TextStyle::default() };
+    image.draw_text("A", TextPosition::TopLeft, &style);
+    // The first column of A leaves its top row empty
+    let white = Some(RGB { r: 255, g: 255, b: 255 });
+    assert_eq!(image.get_pixel(4, 6), white);
+    assert_eq!(image.get_pixel(5, 7), white);
+    assert_eq!(image.get_pixel(4, 4), Some(RGB { r: 0, g: 0, b: 0 }));
+  }
+}
@@ -0,0 +1,257 @@
+use std::path::Path;
+use std::io::{self, BufWriter, Read, Write};
+use std::fs::File;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SampleFormat {
+  PCM16,
+  Float32,
+}
+
+pub struct WavWriter {
+  channels: u16,
+  sample_rate: u32,
+  format: SampleFormat,
+}
+
+impl WavWriter {
+  pub fn new(channels: u16, sample_rate: u32, format: SampleFormat) -> WavWriter {
+    WavWriter { channels, sample_rate, format }
+  }
+
+  fn bytes_per_sample(&self) -> u16 {
+    match self.format {
+      SampleFormat::PCM16 => 2,
+      SampleFormat::Float32 => 4,
+    }
+  }
+
+  fn check_length(&self, len: usize) -> io::Result<()> {
+    // Samples are interleaved, so every frame needs one per channel
+    if self.channels == 0 || !len.is_multiple_of(self.channels as usize) {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{} samples can't be split into {} channels", len, self.channels),
+      ));
+    }
+    Ok(())
+  }
+
+  fn write_header<W: Write>(&self, out: &mut W, sample_count: usize) -> io::Result<()> {
+    // Write the RIFF, fmt and (for float data) fact chunks up to the
+    // start of the sample data
+    let block_align = self.channels * self.bytes_per_sample();
+    let byte_rate = self.sample_rate * block_align as u32;
+
+    let (format_tag, fmt_size, fact_size): (u16, u32, u32) = match self.format {
+      SampleFormat::PCM16 => (1, 16, 0),
+      // Non-PCM formats carry a cbSize field and a fact chunk
+      SampleFormat::Float32 => (3, 18, 12),
+    };
+
+    // Chunk sizes are 32 bit, which limits a file to 4 GiB
+    let too_long = || io::Error::new(
+      io::ErrorKind::InvalidInput,
+      format!("{} samples are too many for a WAV file", sample_count),
+    );
+    let data_size = sample_count.checked_mul(self.bytes_per_sample() as usize)
+      .and_then(|size| u32::try_from(size).ok())
+      .ok_or_else(too_long)?;
+    let riff_size = data_size.checked_add(4 + (8 + fmt_size) + fact_size + 8).ok_or_else(too_long)?;
+    let frames = u32::try_from(sample_count / self.channels as usize).map_err(|_| too_long())?;
+
+    out.write_all(b"RIFF")?;
+    out.write_all(&riff_size.to_le_bytes())?;
+    out.write_all(b"WAVE")?;
+
+    out.write_all(b"fmt ")?;
+    out.write_all(&fmt_size.to_le_bytes())?;
+    out.write_all(&format_tag.to_le_bytes())?;
+    out.write_all(&self.channels.to_le_bytes())?;
+    out.write_all(&self.sample_rate.to_le_bytes())?;
+    out.write_all(&byte_rate.to_le_bytes())?;
+    out.write_all(&block_align.to_le_bytes())?;
+    out.write_all(&(self.bytes_per_sample() * 8).to_le_bytes())?;
+
+    if self.format == SampleFormat::Float32 {
+      out.write_all(&0u16.to_le_bytes())?; // cbSize
+
+      out.write_all(b"fact")?;
+      out.write_all(&4u32.to_le_bytes())?;
+      out.write_all(&frames.to_le_bytes())?;
+    }
+
+    out.write_all(b"data")?;
+    out.write_all(&data_size.to_le_bytes())?;
+    Ok(())
+  }
+
+  pub fn write_i16<W: Write>(&self, out: &mut W, samples: &[i16]) -> io::Result<()> {
+    self.check_length(samples.len())?;
+    self.write_header(out, samples.len())?;
+
+    for sample in samples {
+      match self.format {
+        SampleFormat::PCM16 => out.write_all(&sample.to_le_bytes())?,
+        SampleFormat::Float32 => out.write_all(&(*sample as f32 / 32768.0).to_le_bytes())?,
+      }
+    }
+    Ok(())
+  }
+
+  pub fn write_f32<W: Write>(&self, out: &mut W, samples: &[f32]) -> io::Result<()> {
+    self.check_length(samples.len())?;
+    self.write_header(out, samples.len())?;
+
+    for sample in samples {
+      match self.format {
+        SampleFormat::PCM16 => {
+          let pcm = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+          out.write_all(&pcm.to_le_bytes())?
+        },
+        SampleFormat::Float32 => out.write_all(&sample.to_le_bytes())?,
+      }
+    }
+    Ok(())
+  }
+
+  pub fn write_file_i16(&self, filename: &str, samples: &[i16]) -> io::Result<()> {
+    let path = Path::new(filename);
+    let mut file = BufWriter::new(File::create(path)?);
+    self.write_i16(&mut file, samples)?;
+    file.flush()
+  }
+
+  pub fn write_file_f32(&self, filename: &str, samples: &[f32]) -> io::Result<()> {
+    let path = Path::new(filename);
+    let mut file = BufWriter::new(File::create(path)?);
+    self.write_f32(&mut file, samples)?;
+    file.flush()
+  }
+}
+
+
+fn read_u16<R: Read>(reader: &mut R) -> io::Result<u16> {
+  let mut buf = [0u8; 2];
+  reader.read_exact(&mut buf)?;
+  Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+  let mut buf = [0u8; 4];
+  reader.read_exact(&mut buf)?;
+  Ok(u32::from_le_bytes(buf))
+}
+
+fn skip<R: Read>(reader: &mut R, count: u64) -> io::Result<()> {
+  io::copy(&mut reader.take(count), &mut io::sink())?;
+  Ok(())
+}
+
+pub fn read_wav_header<R: Read>(reader: &mut R) -> io::Result<(u16, u32, SampleFormat)> {
+  //"""Reads a WAV header up to the start of the sample data, returning the
+  //channel count, sample rate and sample format"""
+  let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+  let mut tag = [0u8; 4];
+  reader.read_exact(&mut tag)?;
+  if &tag != b"RIFF" {
+    return Err(invalid("not a RIFF file"));
+  }
+  read_u32(reader)?;
+  reader.read_exact(&mut tag)?;
+  if &tag != b"WAVE" {
+    return Err(invalid("not a WAVE file"));
+  }
+
+  let mut format: Option<(u16, u32, SampleFormat)> = None;
+
+  loop {
+    reader.read_exact(&mut tag)?;
+    let size = read_u32(reader)?;
+    // Chunks are padded to an even length
+    let padded = size as u64 + (size % 2) as u64;
+
+    if &tag == b"fmt " {
+      if size < 16 {
+        return Err(invalid("fmt chunk too short"));
+      }
+      let mut format_tag = read_u16(reader)?;
+      let channels = read_u16(reader)?;
+      let sample_rate = read_u32(reader)?;
+      read_u32(reader)?; // byte rate
+      read_u16(reader)?; // block align
+      let bits = read_u16(reader)?;
+      let mut used = 16;
+
+      if format_tag == 0xFFFE && size >= 40 {
+        // WAVE_FORMAT_EXTENSIBLE keeps the real format in its sub-format
+        skip(reader, 8)?;
+        format_tag = read_u16(reader)?;
+        used = 26;
+      }
+      skip(reader, padded - used)?;
+
+      let sample_format = match (format_tag, bits) {
+        (1, 16) => SampleFormat::PCM16,
+        (3, 32) => SampleFormat::Float32,
+        _ => return Err(invalid("only 16-bit PCM and 32-bit float WAV data is supported")),
+      };
+      format = Some((channels, sample_rate, sample_format));
+    } else if &tag == b"data" {
+      return format.ok_or_else(|| invalid("data chunk before fmt chunk"));
+    } else {
+      skip(reader, padded)?;
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn header(fmt_size: u32) -> Vec<u8> {
+    // RIFF header with a PCM fmt chunk of the given size, then a data chunk
+    let mut wav = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+    wav.extend(fmt_size.to_le_bytes());
+    let mut fmt = Vec::new();
+    fmt.extend(1u16.to_le_bytes());
+    fmt.extend(1u16.to_le_bytes());
+    fmt.extend(8000u32.to_le_bytes());
+    fmt.extend(16000u32.to_le_bytes());
+    fmt.extend(2u16.to_le_bytes());
+    fmt.extend(16u16.to_le_bytes());
+    fmt.resize(fmt_size as usize, 0);
+    wav.extend(fmt);
+    wav.extend(b"data\0\0\0\0");
+    wav
+  }
+
+  #[test]
+  fn reads_written_header() {
+    let mut wav: Vec<u8> = Vec::new();
+    WavWriter::new(2, 11025, SampleFormat::Float32).write_f32(&mut wav, &[0.0; 4]).unwrap();
+    let (channels, sample_rate, format) = read_wav_header(&mut wav.as_slice()).unwrap();
+    assert_eq!((channels, sample_rate, format), (2, 11025, SampleFormat::Float32));
+  }
+
+  #[test]
+  fn short_fmt_chunk_is_invalid() {
+    assert_eq!(read_wav_header(&mut header(16).as_slice()).unwrap(), (1, 8000, SampleFormat::PCM16));
+    let err = read_wav_header(&mut header(12).as_slice()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+  }
+
+  #[test]
+  fn sizes_past_4_gib_are_refused() {
+    let writer = WavWriter::new(1, 8000, SampleFormat::PCM16);
+    // The data chunk itself, then the data chunk plus the headers
+    for samples in [1usize << 31, (u32::MAX as usize - 20) / 2] {
+      let err = writer.write_header(&mut Vec::new(), samples).unwrap_err();
+      assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{} samples", samples);
+    }
+    assert!(writer.write_header(&mut Vec::new(), (u32::MAX as usize - 100) / 2).is_ok());
+  }
+}
@@ -0,0 +1,107 @@
+// Helpers shared by the encode and decode tests
+#![allow(dead_code)]
+
+use russtv::{Image, SSTVEncoder, SSTVSetup, Spec};
+
+
+// Low enough to keep the tests quick, high enough for the fastest modes
+pub const SAMPLE_RATE: u32 = 11025;
+
+// 75% colour bars: white, yellow, cyan, green, magenta, red, blue, black
+pub const BARS: [(u8, u8, u8); 8] = [
+  (191, 191, 191), (191, 191, 0), (0, 191, 191), (0, 191, 0),
+  (191, 0, 191), (191, 0, 0), (0, 0, 191), (0, 0, 0),
+];
+
+
+pub fn colour_bars(width: u32, height: u32) -> Image {
+  // Vertical bars, wide enough that only their edges blur
+  let mut image = Image::new(height, width);
+  for y in 0..height {
+    for x in 0..width {
+      image.set_pixel(x, y, BARS[(x * BARS.len() as u32 / width) as usize]);
+    }
+  }
+  image
+}
+
+
+pub fn bars_for(mode: &Spec) -> Image {
+  colour_bars(mode.LINE_WIDTH as u32, mode.LINE_COUNT as u32)
+}
+
+
+pub fn encode(image: &Image, mode: &Spec) -> Vec<i16> {
+  SSTVEncoder::new(image, mode.clone(), SAMPLE_RATE).unwrap().encode().unwrap()
+}
+
+
+pub fn mean_error(a: &Image, b: &Image) -> f32 {
+  //"""Mean absolute difference of the channels of two images"""
+  assert_eq!((a.width(), a.height()), (b.width(), b.height()));
+  let total: u64 = a.data().iter().zip(b.data())
+    .map(|(x, y)| (*x as i32 - *y as i32).unsigned_abs() as u64)
+    .sum();
+  total as f32 / a.data().len() as f32
+}
+
+
+pub fn greyscale(image: &Image) -> Image {
+  //"""Luma of each pixel, as sent by the black and white modes"""
+  let mut grey = image.clone();
+  for y in 0..image.height() {
+    for x in 0..image.width() {
+      let px = image.get_pixel(x, y).unwrap();
+      let luma = (0.299 * px.r as f32 + 0.587 * px.g as f32 + 0.114 * px.b as f32).round() as u8;
+      grey.set_pixel(x, y, (luma, luma, luma));
+    }
+  }
+  grey
+}
+
+
+pub fn decode_again(image: &Image, mode: &Spec) -> Image {
+  //"""Encodes the image in the mode and decodes it again, checking the VIS
+  //picks the same mode"""
+  let setup = SSTVSetup::from_samples(encode(image, mode), SAMPLE_RATE);
+  let decoder = setup.decode().unwrap();
+  assert_eq!(decoder.mode().NAME, mode.NAME);
+  decoder.decode_image().unwrap()
+}
+
+
+pub fn round_trip(mode: &Spec) -> f32 {
+  //"""Mean error of colour bars sent through the mode"""
+  let image = bars_for(mode);
+  mean_error(&decode_again(&image, mode), &image)
+}
+
+
+pub fn shift_frequency(samples: &[i16], shift: f32) -> Vec<i16> {
+  //"""Moves every tone of the audio by the given number of Hz, as a
+  //mistuned SSB receiver would, using a windowed Hilbert transformer"""
+  const HALF_TAPS: usize = 63;
+  // Only the odd taps are non-zero. They're reversed to run over the
+  // window of audio from the earliest sample
+  let hilbert: Vec<(usize, f32)> = (0..=2 * HALF_TAPS)
+    .map(|k| (k, HALF_TAPS as f32 - k as f32))
+    .filter(|(_, n)| *n as i32 % 2 != 0)
+    .map(|(k, n)| {
+      let window = 0.54 + 0.46 * (std::f32::consts::PI * n / HALF_TAPS as f32).cos();
+      (k, 2.0 / (std::f32::consts::PI * n) * window)
+    })
+    .collect();
+
+  let mut padded = vec![0.0f32; HALF_TAPS];
+  padded.extend(samples.iter().map(|s| *s as f32));
+  padded.extend(vec![0.0; HALF_TAPS]);
+
+  let step = 2.0 * std::f64::consts::PI * shift as f64 / SAMPLE_RATE as f64;
+  (0..samples.len()).map(|i| {
+    let window = &padded[i..i + 2 * HALF_TAPS + 1];
+    let quadrature: f32 = hilbert.iter().map(|(k, h)| h * window[*k]).sum();
+    let phase = step * i as f64;
+    let shifted = samples[i] as f64 * phase.cos() - quadrature as f64 * phase.sin();
+    shifted.round().clamp(i16::MIN as f64, i16::MAX as f64) as i16
+  }).collect()
+}
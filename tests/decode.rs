@@ -0,0 +1,204 @@
+mod common;
+
+use russtv::{Image, SSTVError, SSTVSetup, mode_by_name, vis_code};
+use common::{SAMPLE_RATE, bars_for, decode_again, encode, mean_error, shift_frequency};
+
+
+#[test]
+fn every_row_is_reported_when_audio_ends_with_the_image() {
+  // The encoder stops at the last pixel, with no trailing audio
+  for name in ["Martin 1", "Scottie 1", "PD50", "Robot 36", "SC2-60"] {
+    let mode = mode_by_name(name).unwrap();
+    let setup = SSTVSetup::from_samples(encode(&bars_for(&mode), &mode), SAMPLE_RATE);
+    let mut rows = Vec::new();
+    setup.decode().unwrap().decode_image_rows(|y, _| rows.push(y)).unwrap();
+    assert_eq!(rows, (0..mode.LINE_COUNT).collect::<Vec<_>>(), "{}", name);
+  }
+}
+
+
+#[test]
+fn robot_chroma_follows_the_separator_when_joining_late() {
+  // Joining on an odd line used to swap Cr and Cb for the whole image
+  for name in ["Robot 36", "Robot 12"] {
+    let mode = mode_by_name(name).unwrap();
+    let image = bars_for(&mode);
+    let setup = SSTVSetup::from_samples(encode(&image, &mode), SAMPLE_RATE);
+    let image_start = setup.decode().unwrap().image_start_time().as_secs_f32();
+
+    for join in [10, 11] {
+      // Just before the sync pulse of the line to join at
+      let start = ((image_start + join as f32 * mode.LINE_TIME - 0.002) * SAMPLE_RATE as f32) as usize;
+      // The audio runs out before the image is filled
+      let mut decoded = Image::new(mode.LINE_COUNT as u32, mode.LINE_WIDTH as u32);
+      let decoder = setup.decode_without_header(mode.clone(), start).unwrap();
+      let result = decoder.decode_image_rows(|y, row| { decoded.set_row(y as u32, row); });
+      assert!(matches!(result, Err(SSTVError::TruncatedAudio { .. })));
+
+      // Every row of the bars is the same, so compare the rows that were
+      // sent after the join, less the last which waits for the next line
+      let rows = (mode.LINE_COUNT - join - 1) as u32;
+      let mut total = 0;
+      for y in 0..rows {
+        for x in 0..image.width() {
+          let (a, b) = (decoded.get_pixel(x, y).unwrap(), image.get_pixel(x, y).unwrap());
+          total += a.r.abs_diff(b.r) as u32 + a.g.abs_diff(b.g) as u32 + a.b.abs_diff(b.b) as u32;
+        }
+      }
+      let error = total as f32 / (3 * rows * image.width()) as f32;
+      assert!(error < 25.0, "{} joined at line {}: mean error {}", name, join, error);
+    }
+  }
+}
+
+
+#[test]
+fn slant_is_measured_from_the_sync_pulses() {
+  // Audio labelled with the wrong sample rate, as from a sound card whose
+  // clock runs fast
+  let mode = mode_by_name("Martin 1").unwrap();
+  let samples = encode(&bars_for(&mode), &mode);
+  let setup = SSTVSetup::from_samples(samples, SAMPLE_RATE + 2);
+  let decoder = setup.decode().unwrap();
+  let fit = decoder.measure_slant().unwrap().unwrap();
+  assert!((fit.sample_rate - SAMPLE_RATE as f64).abs() < 0.5, "measured {} Hz", fit.sample_rate);
+}
+
+
+#[test]
+fn only_black_and_white_modes_decode_as_greyscale() {
+  for (name, greyscale) in [("Robot 8 BW", true), ("Robot 36", false), ("Martin 1", false)] {
+    let mode = mode_by_name(name).unwrap();
+    // Grey bars, so the pixels alone can't tell the modes apart
+    let mut image = bars_for(&mode);
+    for y in 0..image.height() {
+      for x in 0..image.width() {
+        let px = image.get_pixel(x, y).unwrap();
+        image.set_pixel(x, y, (px.g, px.g, px.g));
+      }
+    }
+    let setup = SSTVSetup::from_samples(encode(&image, &mode), SAMPLE_RATE);
+    let decoded = setup.decode().unwrap().decode_image().unwrap();
+    assert_eq!(decoded.is_greyscale(), greyscale, "{}", name);
+  }
+}
+
+
+#[test]
+fn audio_ending_mid_image_reports_the_line() {
+  let mode = mode_by_name("Martin 1").unwrap();
+  let mut samples = encode(&bars_for(&mode), &mode);
+  samples.truncate(samples.len() / 2);
+  let cut_time = samples.len() as f32 / SAMPLE_RATE as f32;
+  let setup = SSTVSetup::from_samples(samples, SAMPLE_RATE);
+  let decoder = setup.decode().unwrap();
+
+  // The first line with some of its pixels missing
+  let expected = ((cut_time - decoder.image_start_time().as_secs_f32()) / mode.LINE_TIME) as usize;
+  let mut rows = 0;
+  match decoder.decode_image_rows(|_, _| rows += 1) {
+    Err(SSTVError::TruncatedAudio { line }) => {
+      assert_eq!(line, expected);
+      // Every line before it was still passed on
+      assert_eq!(rows, line);
+    },
+    other => panic!("expected TruncatedAudio, got {:?}", other.map(|_| ())),
+  }
+}
+
+
+#[test]
+fn mistuned_audio_is_corrected() {
+  for name in ["Martin 1", "Robot 36", "Scottie 1", "PD90"] {
+    let mode = mode_by_name(name).unwrap();
+    let image = bars_for(&mode);
+    let in_tune = mean_error(&decode_again(&image, &mode), &image);
+
+    for shift in [80.0, -150.0] {
+      let samples = shift_frequency(&encode(&image, &mode), shift);
+      let setup = SSTVSetup::from_samples(samples, SAMPLE_RATE);
+      let decoder = setup.decode().unwrap();
+      assert_eq!(decoder.mode().NAME, mode.NAME);
+      assert!((decoder.freq_offset() - shift).abs() < 10.0, "{} {:+} Hz: measured {}", name, shift, decoder.freq_offset());
+
+      let error = mean_error(&decoder.decode_image().unwrap(), &image);
+      assert!(error < in_tune + 2.0, "{} {:+} Hz: mean error {}, {} in tune", name, shift, error, in_tune);
+      // Tracking follows the sync pulses without wandering off
+      let tracked = decoder.final_freq_offset().unwrap();
+      assert!((tracked - shift).abs() < 10.0, "{} {:+} Hz: tracked to {}", name, shift, tracked);
+    }
+  }
+}
+
+
+#[test]
+fn headerless_audio_is_classified() {
+  // Pairs of modes sharing a sync timing, told apart by their line count
+  // or the Robot chroma separator
+  for name in ["Martin 1", "Martin 3", "Scottie 1", "Scottie 3", "SC2-60", "SC2-30",
+               "Robot 36", "Robot 36 BW", "Robot 12 BW", "Robot 24 BW", "PD90"] {
+    let mode = mode_by_name(name).unwrap();
+    let samples = encode(&bars_for(&mode), &mode);
+    let image_start = SSTVSetup::from_samples(samples.clone(), SAMPLE_RATE).decode().unwrap().image_start_time();
+    let start = (image_start.as_secs_f32() * SAMPLE_RATE as f32) as usize;
+
+    // Only the audio after the VIS
+    let setup = SSTVSetup::from_samples(samples[start..].to_vec(), SAMPLE_RATE);
+    let candidates = setup.classify(0);
+    assert_eq!(candidates[0].mode.NAME, name);
+    if let Some(runner_up) = candidates.get(1) {
+      assert!(runner_up.confidence < candidates[0].confidence, "{}: tied with {}", name, runner_up.mode.NAME);
+    }
+  }
+}
+
+
+#[test]
+fn every_transmission_in_a_recording_is_decoded() {
+  // The quicker modes, a second of silence apart, the fourth with its
+  // first VIS bit flipped
+  let names = ["Robot 8 BW", "Robot 12", "Robot 12 BW", "Robot 12", "Robot 24 BW",
+               "Robot 24", "Martin 4", "SC2-30", "Robot 8 BW"];
+  let bad = 3;
+  let gap = SAMPLE_RATE as usize;
+
+  let mut samples: Vec<i16> = Vec::new();
+  let mut spans = Vec::new();
+  for (idx, name) in names.iter().enumerate() {
+    let mode = mode_by_name(name).unwrap();
+    let mut transmission = encode(&bars_for(&mode), &mode);
+    if idx == bad {
+      // A 1 bit is sent as 1100 Hz and a 0 as 1300 Hz, the first after the
+      // 610 ms leader and 30 ms start bit
+      let freq = if vis_code(&mode).unwrap() & 1 == 0 { 1100.0 } else { 1300.0 };
+      let bit_start = (0.640 * SAMPLE_RATE as f32) as usize;
+      for (t, sample) in transmission[bit_start..bit_start + (0.030 * SAMPLE_RATE as f32) as usize].iter_mut().enumerate() {
+        let phase = 2.0 * std::f32::consts::PI * freq * t as f32 / SAMPLE_RATE as f32;
+        *sample = (0.8 * i16::MAX as f32 * phase.sin()) as i16;
+      }
+    }
+    spans.push((samples.len(), samples.len() + transmission.len()));
+    samples.extend(transmission);
+    samples.extend(std::iter::repeat_n(0, gap));
+  }
+
+  let results = SSTVSetup::from_samples(samples, SAMPLE_RATE).decode_all();
+  assert_eq!(results.len(), names.len());
+  // Near enough to lose no more than part of a line
+  let tolerance = SAMPLE_RATE as usize / 20;
+
+  for (idx, (result, (start, end))) in results.iter().zip(&spans).enumerate() {
+    if idx == bad {
+      assert!(matches!(result, Err(SSTVError::VisParity)), "{}: {:?}", idx, result.as_ref().map(|_| ()));
+      continue;
+    }
+
+    let decoded = result.as_ref().unwrap();
+    assert_eq!(decoded.mode.NAME, names[idx]);
+    assert!(decoded.start.abs_diff(*start) <= tolerance, "{}: started at {}, sent at {}", idx, decoded.start, start);
+    assert!(decoded.end.abs_diff(*end) <= tolerance, "{}: ended at {}, sent until {}", idx, decoded.end, end);
+    let timestamp = decoded.timestamp.as_secs_f32() * SAMPLE_RATE as f32;
+    assert!((timestamp - *start as f32).abs() <= tolerance as f32, "{}: timestamp {:?}", idx, decoded.timestamp);
+    assert_eq!(decoded.image.height(), decoded.mode.LINE_COUNT as u32);
+  }
+}
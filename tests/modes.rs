@@ -0,0 +1,73 @@
+// Encode and decode colour bars in each mode
+mod common;
+
+use russtv::{SSTVEncoder, SSTVError, mode_by_name};
+use common::{SAMPLE_RATE, bars_for, colour_bars, decode_again, greyscale, mean_error, round_trip};
+
+
+fn check_modes(names: &[&str], max_error: f32) {
+  for name in names {
+    let mode = mode_by_name(name).unwrap();
+    let error = round_trip(&mode);
+    assert!(error < max_error, "{}: mean error {}", name, error);
+  }
+}
+
+
+#[test]
+fn martin_and_scottie_128_line_modes() {
+  check_modes(&["Martin 3", "Martin 4", "Scottie 3", "Scottie 4"], 6.0);
+}
+
+
+#[test]
+fn robot_12_and_24() {
+  // Chroma is sent at half resolution, blurring the edges of the bars
+  check_modes(&["Robot 12", "Robot 24"], 25.0);
+}
+
+
+#[test]
+fn robot_black_and_white_modes() {
+  for name in ["Robot 8 BW", "Robot 12 BW", "Robot 24 BW", "Robot 36 BW"] {
+    let mode = mode_by_name(name).unwrap();
+    let image = greyscale(&bars_for(&mode));
+    let error = mean_error(&decode_again(&image, &mode), &image);
+    assert!(error < 6.0, "{}: mean error {}", name, error);
+  }
+}
+
+
+#[test]
+fn wraase_sc2_modes() {
+  check_modes(&["SC2-30", "SC2-60", "SC2-120", "SC2-180"], 6.0);
+}
+
+
+#[test]
+fn pasokon_modes() {
+  check_modes(&["P3", "P5", "P7"], 4.0);
+}
+
+
+#[test]
+fn pd_modes() {
+  check_modes(&["PD50", "PD90", "PD120", "PD160", "PD180", "PD240", "PD290"], 4.0);
+}
+
+
+#[test]
+fn mmsstv_modes_with_extended_vis() {
+  // The VIS of each is checked as part of the round trip
+  check_modes(&["MR73", "MR90", "MR115", "MR140", "MR175"], 6.0);
+  check_modes(&["MP73", "MP115", "MP140", "MP175"], 4.0);
+  check_modes(&["ML180", "ML240", "ML280", "ML320"], 4.0);
+}
+
+
+#[test]
+fn images_must_be_the_size_of_the_mode() {
+  let mode = mode_by_name("Martin 1").unwrap();
+  let result = SSTVEncoder::new(&colour_bars(320, 240), mode, SAMPLE_RATE);
+  assert!(matches!(result, Err(SSTVError::ImageSize { expected: (320, 256), found: (320, 240), .. })));
+}
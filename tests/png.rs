@@ -0,0 +1,225 @@
+mod common;
+
+use russtv::{Image, PngMetadata};
+use common::{colour_bars, greyscale};
+
+
+fn temp_file(name: &str) -> String {
+  std::env::temp_dir().join(format!("russtv-{}-{}", std::process::id(), name)).to_string_lossy().into_owned()
+}
+
+
+fn write_and_read(image: &Image, name: &str) -> Image {
+  let filename = temp_file(name);
+  image.write_file_png(&filename).unwrap();
+  let read = Image::read_file_png(&filename);
+  std::fs::remove_file(&filename).unwrap();
+  read.unwrap()
+}
+
+
+#[test]
+fn colour_png_round_trip() {
+  let image = colour_bars(320, 256);
+  let read = write_and_read(&image, "colour.png");
+  assert_eq!((read.width(), read.height()), (320, 256));
+  assert_eq!(read.data(), image.data());
+  assert!(!read.is_greyscale());
+}
+
+
+#[test]
+fn greyscale_png_round_trip() {
+  let mut image = greyscale(&colour_bars(160, 120));
+  image.set_greyscale(true);
+  let read = write_and_read(&image, "grey.png");
+  assert_eq!(read.data(), image.data());
+  assert!(read.is_greyscale());
+}
+
+
+fn crc32(bytes: &[u8]) -> u32 {
+  let mut crc = !0u32;
+  for byte in bytes {
+    crc ^= *byte as u32;
+    for _ in 0..8 {
+      crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+    }
+  }
+  !crc
+}
+
+
+fn png_bytes(header: (u32, u32, u8, u8, u8), palette: &[u8], raw: &[u8]) -> Vec<u8> {
+  //"""Builds a PNG of the given (width, height, bit depth, colour type,
+  //interlace) from its filtered scanlines, stored without compression"""
+  let (width, height, depth, color_type, interlace) = header;
+  let mut ihdr = Vec::new();
+  ihdr.extend(width.to_be_bytes());
+  ihdr.extend(height.to_be_bytes());
+  ihdr.extend([depth, color_type, 0, 0, interlace]);
+
+  // zlib header, one final stored block and the Adler-32 of the data
+  let mut idat = vec![0x78, 0x01, 0x01];
+  idat.extend((raw.len() as u16).to_le_bytes());
+  idat.extend((!(raw.len() as u16)).to_le_bytes());
+  idat.extend(raw);
+  let (a, b) = raw.iter().fold((1u32, 0u32), |(a, b), byte| {
+    let a = (a + *byte as u32) % 65521;
+    (a, (b + a) % 65521)
+  });
+  idat.extend(((b << 16) | a).to_be_bytes());
+
+  let mut png = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+  let mut chunks: Vec<(&[u8; 4], Vec<u8>)> = vec![(b"IHDR", ihdr)];
+  if !palette.is_empty() {
+    chunks.push((b"PLTE", palette.to_vec()));
+  }
+  chunks.push((b"IDAT", idat));
+  chunks.push((b"IEND", Vec::new()));
+  for (tag, data) in chunks {
+    png.extend((data.len() as u32).to_be_bytes());
+    let mut tagged = tag.to_vec();
+    tagged.extend(data);
+    png.extend(&tagged);
+    png.extend(crc32(&tagged).to_be_bytes());
+  }
+  png
+}
+
+
+fn read_bytes(bytes: &[u8], name: &str) -> std::io::Result<Image> {
+  let filename = temp_file(name);
+  std::fs::write(&filename, bytes).unwrap();
+  let read = Image::open(&filename);
+  std::fs::remove_file(&filename).unwrap();
+  read
+}
+
+
+fn pixels(image: &Image) -> Vec<(u8, u8, u8)> {
+  image.data().chunks(3).map(|px| (px[0], px[1], px[2])).collect()
+}
+
+
+#[test]
+fn adam7_interlaced_png() {
+  // 3x3 greyscale, so only passes 1, 4, 5, 6 and 7 hold any pixels. The
+  // pixel at (x, y) is 10 * (3y + x + 1)
+  let raw = [
+    0, 10,          // pass 1: (0, 0)
+    0, 30,          // pass 4: (2, 0)
+    0, 70, 90,      // pass 5: (0, 2), (2, 2)
+    0, 20, 0, 80,   // pass 6: (1, 0), then (1, 2)
+    0, 40, 50, 60,  // pass 7: row 1
+  ];
+  let image = read_bytes(&png_bytes((3, 3, 8, 0, 1), &[], &raw), "adam7.png").unwrap();
+  let expected: Vec<(u8, u8, u8)> = (1..=9).map(|v| (10 * v, 10 * v, 10 * v)).collect();
+  assert_eq!(pixels(&image), expected);
+  assert!(image.is_greyscale());
+}
+
+
+#[test]
+fn palette_png() {
+  let palette = [255, 0, 0, 0, 255, 0, 0, 0, 255];
+  // The second row uses the Up filter, adding the row above
+  let raw = [0, 0, 1, 2, 2, 255];
+  let image = read_bytes(&png_bytes((2, 2, 8, 3, 0), &palette, &raw), "palette.png").unwrap();
+  assert_eq!(pixels(&image), vec![(255, 0, 0), (0, 255, 0), (0, 0, 255), (255, 0, 0)]);
+  assert!(!image.is_greyscale());
+
+  // An index past the end of the palette
+  let raw = [0, 0, 3];
+  assert!(read_bytes(&png_bytes((2, 1, 8, 3, 0), &palette, &raw), "badpalette.png").is_err());
+}
+
+
+#[test]
+fn alpha_is_dropped() {
+  // RGBA, the second pixel Sub filtered against the first
+  let raw = [1, 255, 0, 0, 128, 1, 0, 255, 128];
+  let image = read_bytes(&png_bytes((2, 1, 8, 6, 0), &[], &raw), "rgba.png").unwrap();
+  assert_eq!(pixels(&image), vec![(255, 0, 0), (0, 0, 255)]);
+  assert!(!image.is_greyscale());
+
+  // Greyscale with alpha
+  let raw = [0, 50, 255, 200, 0];
+  let image = read_bytes(&png_bytes((2, 1, 8, 4, 0), &[], &raw), "greyalpha.png").unwrap();
+  assert_eq!(pixels(&image), vec![(50, 50, 50), (200, 200, 200)]);
+  assert!(image.is_greyscale());
+}
+
+
+#[test]
+fn sixteen_bit_png_is_refused() {
+  let raw = [0, 255, 255, 0, 0, 0, 0];
+  let err = read_bytes(&png_bytes((1, 1, 16, 2, 0), &[], &raw), "16bit.png").err().unwrap();
+  assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+
+#[test]
+fn ppm_with_comments() {
+  let mut ppm = b"P6\n# made by hand\n2 1\n# 8-bit\n255\n".to_vec();
+  ppm.extend([255, 0, 0, 0, 0, 255]);
+  let image = read_bytes(&ppm, "image.ppm").unwrap();
+  assert_eq!((image.width(), image.height()), (2, 1));
+  assert_eq!(pixels(&image), vec![(255, 0, 0), (0, 0, 255)]);
+
+  // Written and read back by the PPM functions themselves
+  let image = colour_bars(16, 8);
+  let filename = temp_file("bars.ppm");
+  image.write_file(&filename).unwrap();
+  let read = Image::read_file(&filename);
+  std::fs::remove_file(&filename).unwrap();
+  assert_eq!(read.unwrap().data(), image.data());
+}
+
+
+#[test]
+fn unsupported_ppm_is_refused() {
+  for (name, ppm) in [
+    ("ascii.ppm", b"P3 1 1 255\n255 0 0\n".to_vec()),
+    ("16bit.ppm", b"P6 1 1 65535\n\0\0\0\0\0\0".to_vec()),
+    ("short.ppm", b"P6 2 2 255\n\0\0\0".to_vec()),
+  ] {
+    let err = read_bytes(&ppm, name).err().unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData, "{}", name);
+  }
+}
+
+
+#[test]
+fn metadata_png_round_trip() {
+  let mut metadata = PngMetadata::new();
+  metadata.set_text("SSTV Mode", "Robot 36");
+  metadata.set_text("Comment", "73 de Ålesund");
+  metadata.time = Some(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000));
+
+  let image = colour_bars(32, 16);
+  let filename = temp_file("meta.png");
+  image.write_file_png_with_metadata(&filename, &metadata).unwrap();
+  let bytes = std::fs::read(&filename).unwrap();
+  let read = Image::read_png_metadata(&filename);
+  let read_image = Image::read_file_png(&filename);
+  std::fs::remove_file(&filename).unwrap();
+  assert_eq!(read.unwrap(), metadata);
+  assert_eq!(read_image.unwrap().data(), image.data());
+
+  // Walk the chunks checking every CRC and which text chunks were used
+  let mut tags = Vec::new();
+  let mut pos = 8;
+  while pos < bytes.len() {
+    let length = u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap()) as usize;
+    let tagged = &bytes[pos + 4..pos + 8 + length];
+    let crc = u32::from_be_bytes(bytes[pos + 8 + length..pos + 12 + length].try_into().unwrap());
+    assert_eq!(crc32(tagged), crc, "{}", String::from_utf8_lossy(&tagged[..4]));
+    tags.push(String::from_utf8_lossy(&tagged[..4]).into_owned());
+    pos += 12 + length;
+  }
+  assert_eq!(pos, bytes.len());
+  for tag in ["tEXt", "iTXt", "tIME"] {
+    assert!(tags.iter().any(|found| found == tag), "no {} in {:?}", tag, tags);
+  }
+}
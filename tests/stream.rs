@@ -0,0 +1,71 @@
+mod common;
+
+use russtv::{SSTVStream, StreamEvent, WavWriter, SampleFormat, read_wav_header, mode_by_name};
+use common::{SAMPLE_RATE, bars_for, encode, mean_error};
+
+
+#[test]
+fn wav_without_trailing_audio_completes_the_image() {
+  let mode = mode_by_name("Martin 2").unwrap();
+  let image = bars_for(&mode);
+  let mut wav: Vec<u8> = Vec::new();
+  WavWriter::new(1, SAMPLE_RATE, SampleFormat::PCM16).write_i16(&mut wav, &encode(&image, &mode)).unwrap();
+
+  let mut reader = wav.as_slice();
+  let (channels, sample_rate, format) = read_wav_header(&mut reader).unwrap();
+  let mut stream = SSTVStream::new(sample_rate, channels);
+  let mut rows = 0;
+  let mut complete = Vec::new();
+  stream.read_pcm(&mut reader, format, |event| match event {
+    StreamEvent::HeaderDetected(detected) => assert_eq!(detected.NAME, mode.NAME),
+    StreamEvent::LineDecoded(..) => rows += 1,
+    StreamEvent::ImageComplete(decoded) => complete.push(decoded),
+    StreamEvent::ImageTruncated(_) => panic!("image reported as truncated"),
+    StreamEvent::SignalLost => panic!("signal lost"),
+  }).unwrap();
+
+  assert_eq!(rows, mode.LINE_COUNT);
+  assert_eq!(complete.len(), 1);
+  assert!(mean_error(&complete[0], &image) < 8.0);
+}
+
+
+#[test]
+fn finish_reports_an_image_cut_short() {
+  let mode = mode_by_name("Robot 36").unwrap();
+  let samples = encode(&bars_for(&mode), &mode);
+
+  let mut stream = SSTVStream::new(SAMPLE_RATE, 1);
+  let mut events = stream.push_i16(&samples[..samples.len() / 2]);
+  events.extend(stream.finish());
+
+  assert!(matches!(events.first(), Some(StreamEvent::HeaderDetected(_))));
+  assert!(matches!(events.last(), Some(StreamEvent::ImageTruncated(_))));
+  assert!(!events.iter().any(|event| matches!(event, StreamEvent::ImageComplete(_))));
+}
+
+
+#[test]
+fn lost_signal_gives_the_partial_image() {
+  let mode = mode_by_name("Robot 36").unwrap();
+  let image = bars_for(&mode);
+  let mut samples = encode(&image, &mode);
+  // The transmission fades out half way through, into a few seconds of
+  // silence
+  let cut = samples.len() / 2;
+  samples.truncate(cut);
+  samples.extend(std::iter::repeat_n(0, 5 * SAMPLE_RATE as usize));
+
+  let mut stream = SSTVStream::new(SAMPLE_RATE, 1);
+  let events = stream.push_i16(&samples);
+  let lost = events.iter().position(|event| matches!(event, StreamEvent::SignalLost)).expect("signal lost");
+  match events.get(lost + 1) {
+    Some(StreamEvent::ImageTruncated(partial)) => {
+      // The top of the picture came through
+      let top = partial.crop(0, 0, image.width(), 100).unwrap();
+      assert!(mean_error(&top, &image.crop(0, 0, image.width(), 100).unwrap()) < 25.0);
+    },
+    _ => panic!("no partial image after the signal was lost"),
+  }
+  assert!(!events.iter().any(|event| matches!(event, StreamEvent::ImageComplete(_))));
+}